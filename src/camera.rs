@@ -1,20 +1,84 @@
 use crate::engine::*;
 use crate::object::*;
 
+/**
+ * how the camera maps the scene onto the image
+ */
+#[derive(Debug, Clone, Copy)]
+pub enum Projection {
+    Perspective,
+    /**
+     * parallel rays, `width` is the width of the view in world units
+     */
+    Orthographic { width: f64 },
+    /**
+     * the whole sphere around the camera, longitude along x and latitude along y.
+     * ray tracing only, the raster pipeline falls back to perspective
+     */
+    Equirectangular,
+}
+
+/**
+ * where in the pixel, on the lens and in the shutter interval a camera ray starts, every value in [0, 1)
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct CameraSample {
+    pub film: (f64, f64),
+    pub lens: (f64, f64),
+    pub time: f64,
+}
+
+impl CameraSample {
+    /**
+     * the pixel center, through the middle of the lens when the shutter opens
+     */
+    pub const CENTER: CameraSample = CameraSample {
+        film: (0.5, 0.5),
+        lens: (0.5, 0.5),
+        time: 0.0,
+    };
+}
+
 pub struct Camera {
     pub projection_matrix: Mat4,
     pub position: Vec4,
+    /**
+     * columns are right, up and back of the camera in world space
+     */
     pub rotation: Mat4,
     pub view_matrix: Mat4,
 
     /**
-     * fov, in radians
+     * horizontal fov, in radians
      */
     pub fov: f64,
+    /**
+     * width / height
+     */
     pub aspect_ratio: f64,
 
     pub near: f64,
     pub far: f64,
+
+    pub projection: Projection,
+    /**
+     * size of the image in pixels, `generate_ray` maps pixels with it
+     */
+    pub resolution: (usize, usize),
+    /**
+     * diameter of the thin lens, 0 is a pinhole camera without depth of field
+     */
+    pub aperture: f64,
+    /**
+     * distance along the view direction that is in focus when `aperture` is not 0
+     */
+    pub focus_distance: f64,
+    /**
+     * (open, close) times of the shutter, the camera moves with `velocity` while it is open.
+     * None takes every ray at time 0
+     */
+    pub shutter: Option<(f64, f64)>,
+    pub velocity: Vec3,
 }
 
 impl Camera {
@@ -29,7 +93,13 @@ impl Camera {
             near: 0.1,
             far: 100.,
             fov: std::f64::consts::PI / 4.0,
-            aspect_ratio: 3.0 / 4.0,
+            aspect_ratio: 4.0 / 3.0,
+            projection: Projection::Perspective,
+            resolution: (1024, 768),
+            aperture: 0.0,
+            focus_distance: 1.0,
+            shutter: None,
+            velocity: Vec3::ORIGIN,
         };
 
         new_camera.set_up(&up);
@@ -39,12 +109,26 @@ impl Camera {
 
         new_camera
     }
+    /**
+     * also sets the aspect ratio and recomputes the projection matrix
+     */
+    pub fn set_resolution(&mut self, width: usize, height: usize) {
+        self.resolution = (width, height);
+        self.aspect_ratio = width as f64 / height as f64;
+        self.recompute_projection_matrix();
+    }
     pub fn recompute_view_matrix(&mut self) {
-
-        let right = Vec4::cross(self.get_front(), self.get_up());
+        // front and up might not be perpendicular or normalized yet
+        let mut front = self.get_front().xyz();
+        front.normalize();
+        let mut right = Vec3::cross(&front, &self.get_up().xyz());
+        right.normalize();
+        let up = Vec3::cross(&right, &front);
 
         for i in 0..3 {
             self.rotation.value[4 * i] = right.value[i];
+            self.rotation.value[4 * i + 1] = up.value[i];
+            self.rotation.value[4 * i + 2] = -front.value[i];
         }
 
         let position_matrix = Mat4 {
@@ -56,10 +140,12 @@ impl Camera {
             ],
         };
 
-        self.view_matrix = self.rotation * position_matrix;
+        // the inverse of a rotation is its transpose
+        self.view_matrix = self.rotation.transpose() * position_matrix;
     }
     /**
-     * borrowed from https://github.com/mrdoob/three.js/blob/dev/src/math/Matrix4.js
+     * borrowed from https://github.com/mrdoob/three.js/blob/dev/src/math/Matrix4.js,
+     * written row by row since `Mat4 * Vec4` treats `value` as rows
      */
     pub fn recompute_projection_matrix(&mut self) {
         let te = &mut self.projection_matrix.value;
@@ -68,36 +154,118 @@ impl Camera {
             far,
             fov,
             aspect_ratio,
+            projection,
             ..
         } = *self;
+
+        if let Projection::Orthographic { width } = projection {
+            let height = width / aspect_ratio;
+            *te = [
+                2.0 / width, 0.0, 0.0, 0.0,
+                0.0, 2.0 / height, 0.0, 0.0,
+                0.0, 0.0, -2.0 / (far - near), -(far + near) / (far - near),
+                0.0, 0.0, 0.0, 1.0,
+            ];
+            return;
+        }
+
         let tan_theta_2 = (fov / 2.0).tan();
         let width = 2.0 * near * tan_theta_2;
-        let height = width * aspect_ratio;
+        let height = width / aspect_ratio;
         let x = 2.0 * near / width;
         let y = 2.0 * near / height;
 
         let c = -(far + near) / (far - near);
         let d = -2.0 * far * near / (far - near);
 
-        te[0] = x;
-        te[4] = 0.;
-        te[8] = 0.0;
-        te[12] = 0.;
-        te[1] = 0.;
-        te[5] = y;
-        te[9] = 0.0;
-        te[13] = 0.;
-        te[2] = 0.;
-        te[6] = 0.;
-        te[10] = c;
-        te[14] = d;
-        te[3] = 0.;
-        te[7] = 0.;
-        te[11] = -1.;
-        te[15] = 0.;
+        *te = [
+            x, 0.0, 0.0, 0.0,
+            0.0, y, 0.0, 0.0,
+            0.0, 0.0, c, d,
+            0.0, 0.0, -1.0, 0.0,
+        ];
+    }
+    /**
+     * the ray through `pixel` of an image of `resolution`, framed the same as the projection matrix.
+     * `sample` picks the point inside the pixel, on the lens and the time
+     */
+    pub fn generate_ray(&self, pixel: (usize, usize), sample: &CameraSample) -> Ray {
+        let (width, height) = self.resolution;
+        let (x, y) = pixel_to_ndc(
+            pixel.0 as f64 + sample.film.0,
+            pixel.1 as f64 + sample.film.1,
+            width,
+            height,
+        );
+        let time = match self.shutter {
+            Some((open, close)) => open + (close - open) * sample.time,
+            None => 0.0,
+        };
+        let position = self.position.xyz() + self.velocity * time;
+        let right = self.right();
+        let up = self.get_up().xyz();
+        let front = self.get_front().xyz();
+
+        let (origin, mut dir) = match self.projection {
+            Projection::Perspective => {
+                let half_width = (self.fov / 2.0).tan();
+                let half_height = half_width / self.aspect_ratio;
+                // one unit along front, so the focus plane is at `focus_distance` times this
+                let dir = front + right * (x * half_width) + up * (y * half_height);
+                if self.aperture > 0.0 {
+                    let (lens_x, lens_y) = concentric_disk(sample.lens);
+                    let radius = self.aperture / 2.0;
+                    let lens = right * (lens_x * radius) + up * (lens_y * radius);
+                    (position + lens, dir * self.focus_distance - lens)
+                } else {
+                    (position, dir)
+                }
+            }
+            Projection::Orthographic { width } => {
+                let half_width = width / 2.0;
+                let half_height = half_width / self.aspect_ratio;
+                (position + right * (x * half_width) + up * (y * half_height), front)
+            }
+            Projection::Equirectangular => {
+                let longitude = x * std::f64::consts::PI;
+                let latitude = y * std::f64::consts::FRAC_PI_2;
+                let dir = front * (latitude.cos() * longitude.cos())
+                    + right * (latitude.cos() * longitude.sin())
+                    + up * latitude.sin();
+                (position, dir)
+            }
+        };
+        dir.normalize();
+        Ray {
+            origin: Vec4::from(origin),
+            dir: Vec4::from(dir),
+        }
+    }
+    fn right(&self) -> Vec3 {
+        Vec3::new(
+            self.rotation.value[0],
+            self.rotation.value[4],
+            self.rotation.value[8],
+        )
     }
 }
 
+/**
+ * Shirley and Chiu's concentric mapping from the unit square to the unit disk
+ */
+fn concentric_disk(u: (f64, f64)) -> (f64, f64) {
+    let (a, b) = (u.0 * 2.0 - 1.0, u.1 * 2.0 - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, std::f64::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (b, std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (a / b))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
 impl Object for Camera {
     fn set_position(&mut self, pos: &Vec4) {
         self.position = pos.clone();
@@ -107,9 +275,9 @@ impl Object for Camera {
     }
     fn get_front(&self) -> Vec4 {
         Vec4::new(
-            -self.view_matrix.value[2],
-            -self.view_matrix.value[6],
-            -self.view_matrix.value[10],
+            -self.rotation.value[2],
+            -self.rotation.value[6],
+            -self.rotation.value[10],
             1.0,
         )
     }
@@ -120,9 +288,9 @@ impl Object for Camera {
     }
     fn get_up(&self) -> Vec4 {
         Vec4::new(
-            self.view_matrix.value[1],
-            self.view_matrix.value[5],
-            self.view_matrix.value[9],
+            self.rotation.value[1],
+            self.rotation.value[5],
+            self.rotation.value[9],
             1.0,
         )
     }
@@ -132,3 +300,98 @@ impl Object for Camera {
         }
     }
 }
+
+#[cfg(test)]
+fn test_camera(projection: Projection) -> Camera {
+    let position = Vec4::new(3.0, 4.0, 10.0, 1.0);
+    let front = Vec4::new(-0.3, -0.4, -1.0, 1.0).normalize();
+    let mut camera = Camera::new(Vec4::new(0.0, 1.0, 0.0, 1.0), front, position);
+    camera.projection = projection;
+    camera.set_resolution(160, 90);
+    camera
+}
+
+#[cfg(test)]
+fn assert_ray_passes(ray: &Ray, point: &Vec3) {
+    let mut to_point = *point - ray.origin.xyz();
+    to_point.normalize();
+    let miss = Vec3::cross(&to_point, &ray.dir.xyz()).length();
+    assert!(
+        miss < 1e-9 && Vec3::dot(&to_point, &ray.dir.xyz()) > 0.0,
+        "{:?} does not pass {:?}",
+        ray,
+        point
+    );
+}
+
+#[test]
+fn test_generate_ray_matches_projection() {
+    let points = [
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(1.5, 2.0, -1.0),
+        Vec3::new(-2.0, -0.5, 3.0),
+    ];
+    for projection in [Projection::Perspective, Projection::Orthographic { width: 12.0 }].iter() {
+        let camera = test_camera(*projection);
+        for point in points.iter() {
+            // where the raster pipeline puts the point
+            let clip = camera.projection_matrix * (camera.view_matrix * Vec4::from(*point));
+            let (width, height) = camera.resolution;
+            let viewport = Viewport::full(width, height);
+            let (x, y) = viewport.to_pixel(clip.x() / clip.w(), clip.y() / clip.w());
+            assert!(x > 0.0 && x < width as f64 && y > 0.0 && y < height as f64);
+
+            let sample = CameraSample {
+                film: (x.fract(), y.fract()),
+                ..CameraSample::CENTER
+            };
+            let ray = camera.generate_ray((x as usize, y as usize), &sample);
+            assert_ray_passes(&ray, point);
+        }
+    }
+}
+
+#[test]
+fn test_thin_lens_focus() {
+    let mut camera = test_camera(Projection::Perspective);
+    camera.aperture = 0.5;
+    camera.focus_distance = 7.0;
+    let center = camera.generate_ray((100, 30), &CameraSample::CENTER);
+    let cos_theta = Vec3::dot(&center.dir.xyz(), &camera.get_front().xyz());
+    let focus_point = center.origin.xyz() + center.dir.xyz() * (7.0 / cos_theta);
+    let mut spread = 0.0f64;
+    for lens in [(0.1, 0.2), (0.9, 0.5), (0.4, 0.95)].iter() {
+        let sample = CameraSample {
+            lens: *lens,
+            ..CameraSample::CENTER
+        };
+        let ray = camera.generate_ray((100, 30), &sample);
+        assert_ray_passes(&ray, &focus_point);
+        spread = spread.max((ray.origin.xyz() - center.origin.xyz()).length());
+    }
+    assert!(spread > 0.05 && spread <= 0.25, "lens samples spread {}", spread);
+}
+
+#[test]
+fn test_equirectangular_and_shutter() {
+    let mut camera = test_camera(Projection::Equirectangular);
+    let (width, height) = camera.resolution;
+    let front = camera.get_front().xyz();
+    let corner = CameraSample {
+        film: (0.0, 0.0),
+        ..CameraSample::CENTER
+    };
+    let center = camera.generate_ray((width / 2, height / 2), &corner);
+    assert!((Vec3::dot(&center.dir.xyz(), &front) - 1.0).abs() < 1e-9);
+    let top = camera.generate_ray((width / 2, 0), &corner);
+    assert!((Vec3::dot(&top.dir.xyz(), &camera.get_up().xyz()) - 1.0).abs() < 1e-9);
+
+    camera.shutter = Some((0.0, 2.0));
+    camera.velocity = Vec3::new(1.0, 0.0, 0.0);
+    let sample = CameraSample {
+        time: 0.5,
+        ..CameraSample::CENTER
+    };
+    let moved = camera.generate_ray((0, 0), &sample);
+    assert!((moved.origin.x() - 4.0).abs() < 1e-9);
+}
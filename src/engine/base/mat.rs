@@ -1,3 +1,4 @@
+use super::vec3::Vec3;
 use super::vec4::Vec4;
 use std::ops::{Add, Mul};
 
@@ -50,6 +51,12 @@ macro_rules! mat4 {
     }};
 }
 
+impl Mat4 {
+    pub fn transpose(&self) -> Mat4 {
+        mat4!(i, j, self.value[i * 4 + j])
+    }
+}
+
 impl Mul<Vec4> for Mat4 {
     type Output = Vec4;
     fn mul(self, rhs: Vec4) -> Vec4 {
@@ -102,6 +109,65 @@ impl Add<Mat4> for Mat4 {
     }
 }
 
+/**
+ * a 3x3 matrix, row major like `Mat4`. the normal matrix of a model view transform is one
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat3 {
+    pub value: [f64; 9],
+}
+
+impl Mat3 {
+    pub const IDENTITY: Mat3 = Mat3 {
+        value: [1., 0., 0., 0., 1., 0., 0., 0., 1.],
+    };
+}
+
+impl From<Mat4> for Mat3 {
+    /**
+     * the upper left 3x3 block
+     */
+    fn from(m: Mat4) -> Mat3 {
+        let mut value = [0.0; 9];
+        for (i, v) in value.iter_mut().enumerate() {
+            *v = m.value[i / 3 * 4 + i % 3];
+        }
+        Mat3 { value }
+    }
+}
+
+impl Mul<Vec3> for Mat3 {
+    type Output = Vec3;
+    fn mul(self, rhs: Vec3) -> Vec3 {
+        let row = |i: usize| {
+            self.value[i * 3] * rhs.value[0]
+                + self.value[i * 3 + 1] * rhs.value[1]
+                + self.value[i * 3 + 2] * rhs.value[2]
+        };
+        Vec3::new(row(0), row(1), row(2))
+    }
+}
+
+impl Mul<f64> for Mat3 {
+    type Output = Mat3;
+    fn mul(mut self, rhs: f64) -> Mat3 {
+        for v in self.value.iter_mut() {
+            *v *= rhs;
+        }
+        self
+    }
+}
+
+impl Add<Mat3> for Mat3 {
+    type Output = Mat3;
+    fn add(mut self, rhs: Mat3) -> Mat3 {
+        for (v, r) in self.value.iter_mut().zip(rhs.value.iter()) {
+            *v += r;
+        }
+        self
+    }
+}
+
 #[test]
 fn test_mat4_add() {
     let a = mat4!(i, j, (i * 2 + 3 * j) as f64);
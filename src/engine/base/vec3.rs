@@ -12,7 +12,7 @@ impl Vec3 {
         value: [1.0, 1.0, 1.0],
     };
     pub const BLACK:Vec3 = Vec3::ORIGIN;
-    pub fn new(x: f64, y: f64, z: f64) -> Vec3 {
+    pub const fn new(x: f64, y: f64, z: f64) -> Vec3 {
         Vec3 { value: [x, y, z] }
     }
     pub fn normalize(&mut self) {
@@ -42,6 +42,16 @@ impl Vec3 {
     pub fn dot(v1: &Vec3, v2: &Vec3) -> f64 {
         v1.value[0] * v2.value[0] + v1.value[1] * v2.value[1] + v1.value[2] * v2.value[2]
     }
+    /**
+     * component-wise product, mostly for colors
+     */
+    pub fn multiply(v1: &Vec3, v2: &Vec3) -> Vec3 {
+        Vec3::new(
+            v1.value[0] * v2.value[0],
+            v1.value[1] * v2.value[1],
+            v1.value[2] * v2.value[2],
+        )
+    }
     pub fn x(&self) -> f64 {
         self.value[0]
     }
@@ -72,6 +72,15 @@ impl Vec4 {
     }
 }
 
+impl From<Vec3> for Vec4 {
+    /**
+     * a point, w is 1
+     */
+    fn from(v: Vec3) -> Vec4 {
+        Vec4::new(v.x(), v.y(), v.z(), 1.0)
+    }
+}
+
 impl Add for Vec4 {
     type Output = Vec4;
     fn add(self, other: Self) -> Vec4 {
@@ -0,0 +1,272 @@
+use super::base::*;
+use super::program::ShaderData;
+
+/**
+ * the type of one component of a vertex attribute, stored little endian
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComponentType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    F64,
+}
+
+impl ComponentType {
+    pub fn size(self) -> usize {
+        match self {
+            ComponentType::U8 | ComponentType::I8 => 1,
+            ComponentType::U16 | ComponentType::I16 => 2,
+            ComponentType::U32 | ComponentType::I32 | ComponentType::F32 => 4,
+            ComponentType::F64 => 8,
+        }
+    }
+    pub fn is_integer(self) -> bool {
+        !matches!(self, ComponentType::F32 | ComponentType::F64)
+    }
+}
+
+/**
+ * `count` components of type `component`. normalized integers map to [0, 1], or [-1, 1] when signed
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VertexFormat {
+    pub component: ComponentType,
+    pub count: usize,
+    pub normalized: bool,
+}
+
+impl VertexFormat {
+    pub const FLOAT: VertexFormat = VertexFormat::new(ComponentType::F32, 1, false);
+    pub const VEC2: VertexFormat = VertexFormat::new(ComponentType::F32, 2, false);
+    pub const VEC3: VertexFormat = VertexFormat::new(ComponentType::F32, 3, false);
+    pub const VEC4: VertexFormat = VertexFormat::new(ComponentType::F32, 4, false);
+    /**
+     * the layout of a `Vec<Vec4>` turned into a buffer
+     */
+    pub const DVEC4: VertexFormat = VertexFormat::new(ComponentType::F64, 4, false);
+    /**
+     * an 8 bit rgba color
+     */
+    pub const UNORM8X4: VertexFormat = VertexFormat::new(ComponentType::U8, 4, true);
+
+    /**
+     * panics unless `count` is 1 to 4, the shader sees at most a `Vec4`
+     */
+    pub const fn new(component: ComponentType, count: usize, normalized: bool) -> VertexFormat {
+        assert!(
+            count >= 1 && count <= 4,
+            "a vertex format has 1 to 4 components"
+        );
+        VertexFormat {
+            component,
+            count,
+            normalized,
+        }
+    }
+    pub fn size(&self) -> usize {
+        self.component.size() * self.count
+    }
+}
+
+/**
+ * where an attribute sits inside every vertex of a buffer
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VertexLayout {
+    pub offset: usize,
+    pub format: VertexFormat,
+}
+
+/**
+ * raw vertex data, vertex i starts at byte `i * stride`.
+ * `layout` is used when the buffer is drawn as positions and for attributes without a layout of their own,
+ * interleaved buffers give each attribute its own offset
+ */
+#[derive(Debug, Clone)]
+pub struct VertexBuffer {
+    pub data: Vec<u8>,
+    pub stride: usize,
+    pub layout: VertexLayout,
+}
+
+impl VertexBuffer {
+    pub fn new(data: Vec<u8>, stride: usize, layout: VertexLayout) -> VertexBuffer {
+        VertexBuffer {
+            data,
+            stride,
+            layout,
+        }
+    }
+    /**
+     * tightly packed f32 values, `count` per vertex
+     */
+    pub fn from_f32(values: &[f32], count: usize) -> VertexBuffer {
+        let data = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        VertexBuffer::new(
+            data,
+            count * 4,
+            VertexLayout {
+                offset: 0,
+                format: VertexFormat::new(ComponentType::F32, count, false),
+            },
+        )
+    }
+    /**
+     * number of whole vertices in the buffer
+     */
+    pub fn len(&self) -> usize {
+        self.data.len().checked_div(self.stride).unwrap_or(0)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /**
+     * the components of vertex `vertex` at `layout`, converted to f64
+     */
+    pub fn components(&self, vertex: usize, layout: &VertexLayout) -> Vec<f64> {
+        let VertexFormat {
+            component,
+            count,
+            normalized,
+        } = layout.format;
+        let start = vertex * self.stride + layout.offset;
+        let bytes = &self.data[start..start + layout.format.size()];
+        bytes
+            .chunks_exact(component.size())
+            .take(count)
+            .map(|b| {
+                let (value, max) = match component {
+                    ComponentType::U8 => (b[0] as f64, u8::MAX as f64),
+                    ComponentType::I8 => (b[0] as i8 as f64, i8::MAX as f64),
+                    ComponentType::U16 => {
+                        (u16::from_le_bytes([b[0], b[1]]) as f64, u16::MAX as f64)
+                    }
+                    ComponentType::I16 => {
+                        (i16::from_le_bytes([b[0], b[1]]) as f64, i16::MAX as f64)
+                    }
+                    ComponentType::U32 => (
+                        u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                        u32::MAX as f64,
+                    ),
+                    ComponentType::I32 => (
+                        i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                        i32::MAX as f64,
+                    ),
+                    ComponentType::F32 => {
+                        (f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64, 1.0)
+                    }
+                    ComponentType::F64 => {
+                        let mut bytes = [0; 8];
+                        bytes.copy_from_slice(b);
+                        (f64::from_le_bytes(bytes), 1.0)
+                    }
+                };
+                if normalized && component.is_integer() {
+                    (value / max).max(-1.0)
+                } else {
+                    value
+                }
+            })
+            .collect()
+    }
+    /**
+     * the attribute of vertex `vertex` as the shader sees it, by number of components:
+     * a `Float`, `Vec2`, `Vec3` or `Vec4`. one integer component that is not normalized is an `Int`
+     */
+    pub fn read(&self, vertex: usize, layout: &VertexLayout) -> ShaderData {
+        let v = self.components(vertex, layout);
+        match v.len() {
+            1 if layout.format.component.is_integer() && !layout.format.normalized => {
+                ShaderData::Int(v[0] as i64)
+            }
+            1 => ShaderData::Float(v[0]),
+            2 => ShaderData::Vec2(Vec2::new(v[0], v[1])),
+            3 => ShaderData::Vec3(Vec3::new(v[0], v[1], v[2])),
+            _ => ShaderData::Vec4(Vec4::new(v[0], v[1], v[2], v[3])),
+        }
+    }
+    /**
+     * the position of vertex `vertex`, missing components are filled from (0, 0, 0, 1)
+     */
+    pub fn read_position(&self, vertex: usize) -> Vec4 {
+        let mut value = [0.0, 0.0, 0.0, 1.0];
+        for (i, v) in self
+            .components(vertex, &self.layout)
+            .into_iter()
+            .enumerate()
+        {
+            value[i] = v;
+        }
+        Vec4 { value }
+    }
+}
+
+impl From<Vec<Vec4>> for VertexBuffer {
+    fn from(vertices: Vec<Vec4>) -> VertexBuffer {
+        let data = vertices
+            .iter()
+            .flat_map(|v| v.value.iter().flat_map(|c| c.to_le_bytes()))
+            .collect();
+        VertexBuffer::new(
+            data,
+            32,
+            VertexLayout {
+                offset: 0,
+                format: VertexFormat::DVEC4,
+            },
+        )
+    }
+}
+
+#[test]
+fn test_vertex_buffer() {
+    // position xyz as f32, a normalized rgba color and a material id, 20 bytes per vertex
+    let mut data = vec![];
+    for i in 0..2 {
+        for v in [i as f32, 2.0, -1.5].iter() {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        data.extend_from_slice(&[255, 0, 51, 255]);
+        data.extend_from_slice(&(7i16 - i as i16).to_le_bytes());
+        data.extend_from_slice(&(-32767i16).to_le_bytes());
+    }
+    let layout = |offset, format| VertexLayout { offset, format };
+    let buffer = VertexBuffer::new(data, 20, layout(0, VertexFormat::VEC3));
+    assert_eq!(buffer.len(), 2);
+
+    let position = buffer.read_position(1);
+    assert_eq!(position.value, [1.0, 2.0, -1.5, 1.0]);
+    match buffer.read(0, &layout(12, VertexFormat::UNORM8X4)) {
+        ShaderData::Vec4(color) => assert_eq!(color.value, [1.0, 0.0, 0.2, 1.0]),
+        other => panic!("a color read as {:?}", other),
+    }
+    match buffer.read(
+        1,
+        &layout(16, VertexFormat::new(ComponentType::I16, 1, false)),
+    ) {
+        ShaderData::Int(id) => assert_eq!(id, 6),
+        other => panic!("an id read as {:?}", other),
+    }
+    match buffer.read(
+        0,
+        &layout(16, VertexFormat::new(ComponentType::I16, 2, true)),
+    ) {
+        ShaderData::Vec2(v) => assert_eq!((v.x(), v.y()), (7.0 / 32767.0, -1.0)),
+        other => panic!("a snorm pair read as {:?}", other),
+    }
+
+    let vertices = VertexBuffer::from(vec![Vec4::new(1.0, 2.0, 3.0, 0.5)]);
+    assert_eq!(vertices.read_position(0).value, [1.0, 2.0, 3.0, 0.5]);
+    assert_eq!(VertexBuffer::from_f32(&[0.0; 6], 2).len(), 3);
+}
+
+#[test]
+#[should_panic(expected = "a vertex format has 1 to 4 components")]
+fn test_vertex_format_count() {
+    VertexFormat::new(ComponentType::F32, 5, false);
+}
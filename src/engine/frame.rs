@@ -1,59 +1,344 @@
-use super::program::ShaderData;
-use crate::engine::base::*;
+use super::texture::Texture;
+use std::ops::Range;
 
-#[derive(Debug)]
+/**
+ * a render target as planes of pixels, row by row from the top left corner.
+ * every plane has `width * height` entries, color, depth and stencil have `samples` of them per pixel
+ * with the samples of pixel i starting at `i * samples`
+ */
+#[derive(Debug, Clone)]
 pub struct Frame {
     pub width: usize,
     pub height: usize,
-    pub buffer: Vec<PixelBuffer>,
+    /**
+     * 1, 2, 4 or 8, the sample positions are those of `sample_pattern`
+     */
+    pub samples: usize,
+    pub color: Vec<(u8, u8, u8, u8)>,
+    pub depth: Vec<f32>,
+    pub stencil: Vec<u8>,
+    /**
+     * extra rgba planes for a g-buffer, like normals or albedo. they hold the outputs of
+     * `Shader::fragment_outputs` after the color, one value per pixel
+     */
+    pub attachments: Vec<Vec<[f32; 4]>>,
+    /**
+     * the triangle of the last draw that covers each pixel
+     */
+    pub visibility: Vec<Visibility>,
 }
 
-#[derive(Debug)]
-pub struct PixelBuffer {
-    pub coord: Vec2,
-    pub color: (u8, u8, u8, u8),
-    pub z: u8, //z buffer
-    pub varying: Option<Vec<ShaderData>>,
+/**
+ * a triangle id and the screen space weights of its first two vertices, the third one is 1 - both.
+ * the varyings are only interpolated from it once the depth test has picked the pixel's triangle
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Visibility {
+    pub triangle: u32,
+    pub barycentric: [f32; 2],
+}
+
+impl Visibility {
+    pub const NONE: Visibility = Visibility {
+        triangle: u32::MAX,
+        barycentric: [0.0; 2],
+    };
+
+    pub fn new(triangle: usize, alpha: f64, beta: f64) -> Visibility {
+        Visibility {
+            triangle: triangle as u32,
+            barycentric: [alpha as f32, beta as f32],
+        }
+    }
+    pub fn is_none(&self) -> bool {
+        self.triangle == u32::MAX
+    }
+    pub fn weights(&self) -> [f64; 3] {
+        let [alpha, beta] = self.barycentric.map(f64::from);
+        [alpha, beta, 1.0 - alpha - beta]
+    }
 }
 
 impl Frame {
-    pub fn clear(&mut self) {
-        for i in 0..self.buffer.len() {
-            self.buffer[i].color = (0, 0, 0, 0);
-            self.buffer[i].varying = None;
-            self.buffer[i].z = 0;
+    pub fn new(width: usize, height: usize) -> Self {
+        Frame::multisample(width, height, 1)
+    }
+    /**
+     * a frame with `samples` color, depth and stencil samples per pixel
+     */
+    pub fn multisample(width: usize, height: usize, samples: usize) -> Self {
+        assert!(
+            [1, 2, 4, 8].contains(&samples),
+            "{} samples per pixel are not supported",
+            samples
+        );
+        let size = width * height;
+        Frame {
+            width,
+            height,
+            samples,
+            color: vec![(0, 0, 0, 0); size * samples],
+            depth: vec![1.0; size * samples],
+            stencil: vec![0; size * samples],
+            attachments: vec![],
+            visibility: vec![Visibility::NONE; size],
         }
     }
-    pub fn get(&self, coord: &(usize, usize)) -> Option<&PixelBuffer> {
-        if coord.0 >= self.width || coord.1 >= self.height {
-            None
-        } else {
-            self.buffer.get(coord.1 * self.width + coord.0)
+    /**
+     * adds a g-buffer plane cleared to 0 and returns its index in `attachments`
+     */
+    pub fn add_attachment(&mut self) -> usize {
+        self.attachments
+            .push(vec![[0.0; 4]; self.width * self.height]);
+        self.attachments.len() - 1
+    }
+    pub fn clear(&mut self) {
+        self.color
+            .iter_mut()
+            .for_each(|color| *color = (0, 0, 0, 0));
+        for attachment in self.attachments.iter_mut() {
+            attachment.iter_mut().for_each(|value| *value = [0.0; 4]);
         }
+        self.clear_depth(1.0);
+        self.clear_visibility();
+    }
+    pub fn clear_depth(&mut self, depth: f32) {
+        self.depth.iter_mut().for_each(|z| *z = depth);
+    }
+    pub fn clear_stencil(&mut self, stencil: u8) {
+        self.stencil.iter_mut().for_each(|s| *s = stencil);
+    }
+    pub fn clear_visibility(&mut self) {
+        self.visibility
+            .iter_mut()
+            .for_each(|v| *v = Visibility::NONE);
     }
-    pub fn get_mut(&mut self, coord: &(usize, usize)) -> Option<&mut PixelBuffer> {
+    /**
+     * the position of pixel `coord` in the planes
+     */
+    pub fn index(&self, coord: &(usize, usize)) -> Option<usize> {
         if coord.0 >= self.width || coord.1 >= self.height {
             None
         } else {
-            self.buffer.get_mut(coord.1 * self.width + coord.0)
+            Some(coord.1 * self.width + coord.0)
         }
     }
-    pub fn new(width: usize, height: usize) -> Self {
-        let mut buffer = vec![];
-        for y in 0..height {
-            for x in 0..width {
-                buffer.push(PixelBuffer {
-                    coord: Vec2::new(x as f64, y as f64),
-                    color: (0, 0, 0, 0),
-                    z: 0,
-                    varying: None,
-                })
+    /**
+     * the color of pixel `coord`, the average of its samples
+     */
+    pub fn get_color(&self, coord: &(usize, usize)) -> Option<(u8, u8, u8, u8)> {
+        self.index(coord).map(|i| self.resolve_color(i))
+    }
+    /**
+     * the depth of the first sample of pixel `coord`
+     */
+    pub fn get_depth(&self, coord: &(usize, usize)) -> Option<f32> {
+        self.index(coord).map(|i| self.depth[i * self.samples])
+    }
+    fn resolve_color(&self, index: usize) -> (u8, u8, u8, u8) {
+        if self.samples == 1 {
+            return self.color[index];
+        }
+        let samples = &self.color[index * self.samples..(index + 1) * self.samples];
+        let mut sum = [0usize; 4];
+        for (r, g, b, a) in samples.iter() {
+            for (channel, value) in sum.iter_mut().zip([r, g, b, a].iter()) {
+                *channel += **value as usize;
             }
         }
+        let average = sum.map(|channel| ((channel + self.samples / 2) / self.samples) as u8);
+        (average[0], average[1], average[2], average[3])
+    }
+    /**
+     * a single sampled copy with the average color of every pixel,
+     * depth and stencil are those of the first sample
+     */
+    pub fn resolve(&self) -> Frame {
+        let size = self.width * self.height;
+        Frame {
+            width: self.width,
+            height: self.height,
+            samples: 1,
+            color: (0..size).map(|i| self.resolve_color(i)).collect(),
+            depth: self.depth.iter().step_by(self.samples).copied().collect(),
+            stencil: self.stencil.iter().step_by(self.samples).copied().collect(),
+            attachments: self.attachments.clone(),
+            visibility: self.visibility.clone(),
+        }
+    }
+    /**
+     * the resolved colors as a texture for a later pass
+     */
+    pub fn color_texture(&self) -> Texture {
+        let texels = (0..self.width * self.height)
+            .map(|i| {
+                let (r, g, b, a) = self.resolve_color(i);
+                [r, g, b, a].map(|channel| f64::from(channel) / 255.0)
+            })
+            .collect();
+        Texture::new(self.width, self.height, texels)
+    }
+    /**
+     * attachment `index` as a texture, the values are not clamped to [0, 1]
+     */
+    pub fn attachment_texture(&self, index: usize) -> Texture {
+        let texels = self.attachments[index]
+            .iter()
+            .map(|value| value.map(f64::from))
+            .collect();
+        Texture::new(self.width, self.height, texels)
+    }
+    /**
+     * the depth of the first sample of every pixel in r, g and b, like a depth texture in OpenGL.
+     * a shadow map compares against its r
+     */
+    pub fn depth_texture(&self) -> Texture {
+        let texels = self
+            .depth
+            .iter()
+            .step_by(self.samples)
+            .map(|z| {
+                let z = f64::from(*z);
+                [z, z, z, 1.0]
+            })
+            .collect();
+        Texture::new(self.width, self.height, texels)
+    }
+    /**
+     * the stencil of the first sample of every pixel in r, g and b, divided by 255
+     */
+    pub fn stencil_texture(&self) -> Texture {
+        let texels = self
+            .stencil
+            .iter()
+            .step_by(self.samples)
+            .map(|s| {
+                let s = f64::from(*s) / 255.0;
+                [s, s, s, 1.0]
+            })
+            .collect();
+        Texture::new(self.width, self.height, texels)
+    }
+    /**
+     * a copy of the pixels in `x` and `y`, the visibility of the copy starts empty
+     */
+    pub fn region(&self, x: Range<usize>, y: Range<usize>) -> Frame {
+        let (width, samples) = (self.width, self.samples);
         Frame {
-            buffer,
+            width: x.len(),
+            height: y.len(),
+            samples,
+            color: copy_rows(&self.color, width * samples, &scale(&x, samples), &y),
+            depth: copy_rows(&self.depth, width * samples, &scale(&x, samples), &y),
+            stencil: copy_rows(&self.stencil, width * samples, &scale(&x, samples), &y),
+            attachments: self
+                .attachments
+                .iter()
+                .map(|plane| copy_rows(plane, width, &x, &y))
+                .collect(),
+            visibility: vec![Visibility::NONE; x.len() * y.len()],
+        }
+    }
+    /**
+     * writes every plane of `region` back with its top left corner at `origin`
+     */
+    pub fn set_region(&mut self, origin: (usize, usize), region: &Frame) {
+        let (width, samples) = (self.width * self.samples, self.samples);
+        let sample_origin = (origin.0 * samples, origin.1);
+        let region_width = region.width * samples;
+        paste_rows(
+            &mut self.color,
             width,
-            height,
+            sample_origin,
+            &region.color,
+            region_width,
+        );
+        paste_rows(
+            &mut self.depth,
+            width,
+            sample_origin,
+            &region.depth,
+            region_width,
+        );
+        paste_rows(
+            &mut self.stencil,
+            width,
+            sample_origin,
+            &region.stencil,
+            region_width,
+        );
+        let width = self.width;
+        paste_rows(
+            &mut self.visibility,
+            width,
+            origin,
+            &region.visibility,
+            region.width,
+        );
+        for (plane, from) in self.attachments.iter_mut().zip(region.attachments.iter()) {
+            paste_rows(plane, width, origin, from, region.width);
         }
     }
 }
+
+/**
+ * the entries of the pixels in `x` in a plane with `samples` entries per pixel
+ */
+fn scale(x: &Range<usize>, samples: usize) -> Range<usize> {
+    x.start * samples..x.end * samples
+}
+
+fn copy_rows<T: Copy>(plane: &[T], width: usize, x: &Range<usize>, y: &Range<usize>) -> Vec<T> {
+    let mut res = Vec::with_capacity(x.len() * y.len());
+    for row in y.clone() {
+        res.extend_from_slice(&plane[row * width..][x.clone()]);
+    }
+    res
+}
+
+fn paste_rows<T: Copy>(
+    plane: &mut [T],
+    width: usize,
+    origin: (usize, usize),
+    from: &[T],
+    from_width: usize,
+) {
+    for (row, values) in from.chunks_exact(from_width.max(1)).enumerate() {
+        let start = (origin.1 + row) * width + origin.0;
+        plane[start..start + from_width].copy_from_slice(values);
+    }
+}
+
+/**
+ * pixel coordinates to normalized device coordinates, the inverse of `Viewport::full(width, height).to_pixel`.
+ * pixels start at the top left corner, y = 1 is the top row
+ */
+pub fn pixel_to_ndc(x: f64, y: f64, width: usize, height: usize) -> (f64, f64) {
+    (x / width as f64 * 2.0 - 1.0, 1.0 - y / height as f64 * 2.0)
+}
+
+#[test]
+fn test_frame_region() {
+    let mut frame = Frame::new(5, 4);
+    let normals = frame.add_attachment();
+    for i in 0..20 {
+        frame.color[i] = (i as u8, 0, 0, 255);
+        frame.attachments[normals][i] = [i as f32; 4];
+    }
+    let mut region = frame.region(1..4, 2..4);
+    assert_eq!((region.width, region.height), (3, 2));
+    assert_eq!(region.get_color(&(0, 0)), Some((11, 0, 0, 255)));
+    assert_eq!(region.attachments[normals][5], [18.0; 4]);
+    assert!(region.visibility.iter().all(Visibility::is_none));
+
+    region.depth[4] = 0.5;
+    region.visibility[4] = Visibility::new(7, 0.25, 0.5);
+    frame.set_region((1, 2), &region);
+    assert_eq!(frame.get_depth(&(2, 3)), Some(0.5));
+    assert_eq!(frame.visibility[17].weights(), [0.25, 0.5, 0.25]);
+    assert_eq!(frame.get_color(&(0, 3)), Some((15, 0, 0, 255)));
+
+    frame.clear();
+    assert_eq!(frame.attachments[normals][12], [0.0; 4]);
+    assert!(frame.visibility[17].is_none());
+}
@@ -1,7 +1,10 @@
 
 
+mod buffer;
 mod frame;
 mod program;
+mod shader;
+mod texture;
 mod base;
 mod pipeline;
 mod raytracing;
@@ -10,4 +13,7 @@ pub use raytracing::*;
 pub use pipeline::*;
 pub use base::*;
 pub use program::*;
-pub use frame::*;
\ No newline at end of file
+pub use shader::*;
+pub use buffer::*;
+pub use frame::*;
+pub use texture::*;
\ No newline at end of file
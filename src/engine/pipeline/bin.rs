@@ -0,0 +1,73 @@
+use std::ops::Range;
+
+/**
+ * a rectangle of the frame and the triangles that may cover it, in draw order
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bin {
+    pub x: Range<usize>,
+    pub y: Range<usize>,
+    pub triangles: Vec<usize>,
+}
+
+/**
+ * sorts triangles given by their pixel coordinates into the bins their bounding boxes touch.
+ * bins without triangles are left out
+ */
+pub fn bin_triangles(
+    triangles: &[[(f64, f64); 3]],
+    width: usize,
+    height: usize,
+    bin_size: usize,
+) -> Vec<Bin> {
+    let bin_size = bin_size.max(1);
+    let (columns, rows) = (width.div_ceil(bin_size), height.div_ceil(bin_size));
+    let mut bins: Vec<Bin> = (0..rows * columns)
+        .map(|i| {
+            let (x, y) = (i % columns * bin_size, i / columns * bin_size);
+            Bin {
+                x: x..(x + bin_size).min(width),
+                y: y..(y + bin_size).min(height),
+                triangles: vec![],
+            }
+        })
+        .collect();
+    if bins.is_empty() {
+        return bins;
+    }
+
+    for (i, triangle) in triangles.iter().enumerate() {
+        let (mut min, mut max) = (triangle[0], triangle[0]);
+        for (x, y) in triangle.iter() {
+            min = (min.0.min(*x), min.1.min(*y));
+            max = (max.0.max(*x), max.1.max(*y));
+        }
+        if max.0 < 0.0 || max.1 < 0.0 || min.0 >= width as f64 || min.1 >= height as f64 {
+            continue;
+        }
+        let bin = |v: f64, count: usize| ((v.max(0.0) as usize) / bin_size).min(count - 1);
+        for row in bin(min.1, rows)..=bin(max.1, rows) {
+            for column in bin(min.0, columns)..=bin(max.0, columns) {
+                bins[row * columns + column].triangles.push(i);
+            }
+        }
+    }
+
+    bins.retain(|bin| !bin.triangles.is_empty());
+    bins
+}
+
+#[test]
+fn test_bin_triangles() {
+    let triangles = [
+        [(1.0, 1.0), (6.0, 1.0), (1.0, 6.0)],
+        [(2.0, 2.0), (30.0, 3.0), (4.0, 12.0)],
+        [(-5.0, -5.0), (-1.0, -5.0), (-5.0, -1.0)],
+    ];
+    let bins = bin_triangles(&triangles, 20, 16, 8);
+    // the second triangle reaches out of the frame on the right
+    assert_eq!(bins.len(), 6);
+    assert_eq!(bins[0].triangles, vec![0, 1]);
+    assert_eq!((bins[2].x.clone(), bins[2].y.clone()), (16..20, 0..8));
+    assert!(bins[1..].iter().all(|bin| bin.triangles == vec![1]));
+}
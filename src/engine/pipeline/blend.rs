@@ -0,0 +1,223 @@
+/**
+ * what a source or destination color is multiplied by before the blend equation
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+    ConstantColor,
+    OneMinusConstantColor,
+    ConstantAlpha,
+    OneMinusConstantAlpha,
+    /**
+     * min(src alpha, 1 - dst alpha) for rgb, 1 for alpha
+     */
+    SrcAlphaSaturate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendEquation {
+    /**
+     * src * src factor + dst * dst factor
+     */
+    Add,
+    /**
+     * src * src factor - dst * dst factor
+     */
+    Subtract,
+    /**
+     * dst * dst factor - src * src factor
+     */
+    ReverseSubtract,
+    /**
+     * min(src, dst), the factors are ignored
+     */
+    Min,
+    /**
+     * max(src, dst), the factors are ignored
+     */
+    Max,
+}
+
+/**
+ * the blend settings of a `Context`, colors are rgba in [0, 1].
+ * rgb and alpha have their own factors and equations like glBlendFuncSeparate
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlendState {
+    /**
+     * without blending the fragment color replaces the pixel
+     */
+    pub enabled: bool,
+    pub src_rgb: BlendFactor,
+    pub dst_rgb: BlendFactor,
+    pub src_alpha: BlendFactor,
+    pub dst_alpha: BlendFactor,
+    pub equation_rgb: BlendEquation,
+    pub equation_alpha: BlendEquation,
+    pub constant: [f64; 4],
+    /**
+     * channels with a false mask keep the value in the buffer, blending or not
+     */
+    pub write_mask: [bool; 4],
+    /**
+     * a multisampled fragment with alpha a only covers the first round(a * samples) of its samples
+     */
+    pub alpha_to_coverage: bool,
+}
+
+impl Default for BlendState {
+    fn default() -> BlendState {
+        BlendState {
+            enabled: false,
+            src_rgb: BlendFactor::One,
+            dst_rgb: BlendFactor::Zero,
+            src_alpha: BlendFactor::One,
+            dst_alpha: BlendFactor::Zero,
+            equation_rgb: BlendEquation::Add,
+            equation_alpha: BlendEquation::Add,
+            constant: [0.0; 4],
+            write_mask: [true; 4],
+            alpha_to_coverage: false,
+        }
+    }
+}
+
+impl BlendState {
+    /**
+     * src over dst for colors that are not premultiplied
+     */
+    pub const ALPHA: BlendState = BlendState {
+        enabled: true,
+        src_rgb: BlendFactor::SrcAlpha,
+        dst_rgb: BlendFactor::OneMinusSrcAlpha,
+        src_alpha: BlendFactor::One,
+        dst_alpha: BlendFactor::OneMinusSrcAlpha,
+        equation_rgb: BlendEquation::Add,
+        equation_alpha: BlendEquation::Add,
+        constant: [0.0; 4],
+        write_mask: [true; 4],
+        alpha_to_coverage: false,
+    };
+    /**
+     * src + dst, for particles and glows
+     */
+    pub const ADDITIVE: BlendState = BlendState {
+        enabled: true,
+        src_rgb: BlendFactor::One,
+        dst_rgb: BlendFactor::One,
+        src_alpha: BlendFactor::One,
+        dst_alpha: BlendFactor::One,
+        equation_rgb: BlendEquation::Add,
+        equation_alpha: BlendEquation::Add,
+        constant: [0.0; 4],
+        write_mask: [true; 4],
+        alpha_to_coverage: false,
+    };
+
+    /**
+     * the color to store for a fragment `src` drawn over `dst`, masked channels keep `dst`
+     */
+    pub fn apply(&self, src: [f64; 4], dst: [f64; 4]) -> [f64; 4] {
+        let mut res = src;
+        if self.enabled {
+            for (i, channel) in res.iter_mut().enumerate() {
+                let (src_factor, dst_factor, equation) = if i < 3 {
+                    (self.src_rgb, self.dst_rgb, self.equation_rgb)
+                } else {
+                    (self.src_alpha, self.dst_alpha, self.equation_alpha)
+                };
+                let s = src[i] * self.factor(src_factor, i, &src, &dst);
+                let d = dst[i] * self.factor(dst_factor, i, &src, &dst);
+                *channel = match equation {
+                    BlendEquation::Add => s + d,
+                    BlendEquation::Subtract => s - d,
+                    BlendEquation::ReverseSubtract => d - s,
+                    BlendEquation::Min => src[i].min(dst[i]),
+                    BlendEquation::Max => src[i].max(dst[i]),
+                };
+            }
+        }
+        for (i, channel) in res.iter_mut().enumerate() {
+            *channel = if self.write_mask[i] {
+                channel.clamp(0.0, 1.0)
+            } else {
+                dst[i]
+            };
+        }
+        res
+    }
+    fn factor(&self, factor: BlendFactor, channel: usize, src: &[f64; 4], dst: &[f64; 4]) -> f64 {
+        match factor {
+            BlendFactor::Zero => 0.0,
+            BlendFactor::One => 1.0,
+            BlendFactor::SrcColor => src[channel],
+            BlendFactor::OneMinusSrcColor => 1.0 - src[channel],
+            BlendFactor::DstColor => dst[channel],
+            BlendFactor::OneMinusDstColor => 1.0 - dst[channel],
+            BlendFactor::SrcAlpha => src[3],
+            BlendFactor::OneMinusSrcAlpha => 1.0 - src[3],
+            BlendFactor::DstAlpha => dst[3],
+            BlendFactor::OneMinusDstAlpha => 1.0 - dst[3],
+            BlendFactor::ConstantColor => self.constant[channel],
+            BlendFactor::OneMinusConstantColor => 1.0 - self.constant[channel],
+            BlendFactor::ConstantAlpha => self.constant[3],
+            BlendFactor::OneMinusConstantAlpha => 1.0 - self.constant[3],
+            BlendFactor::SrcAlphaSaturate if channel == 3 => 1.0,
+            BlendFactor::SrcAlphaSaturate => src[3].min(1.0 - dst[3]),
+        }
+    }
+}
+
+/**
+ * a color in [0, 1] to the 8 bit value stored in the frame, 1.0 is 255
+ */
+pub fn to_u8(value: f64) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[test]
+fn test_blend() {
+    let src = [1.0, 0.0, 0.0, 0.25];
+    let dst = [0.0, 0.0, 1.0, 1.0];
+    assert_eq!(BlendState::default().apply(src, dst), src);
+
+    let over = BlendState::ALPHA.apply(src, dst);
+    assert_eq!(over, [0.25, 0.0, 0.75, 1.0]);
+
+    // additive light saturates instead of wrapping around
+    let added = BlendState::ADDITIVE.apply([0.75, 0.5, 0.0, 1.0], [0.5, 0.25, 0.0, 1.0]);
+    assert_eq!(added, [1.0, 0.75, 0.0, 1.0]);
+
+    let state = BlendState {
+        equation_rgb: BlendEquation::Max,
+        src_rgb: BlendFactor::ConstantColor,
+        write_mask: [true, true, false, true],
+        ..BlendState::ALPHA
+    };
+    assert_eq!(
+        state.apply([0.5, 0.5, 0.5, 0.5], [0.25, 0.75, 0.25, 0.5]),
+        [0.5, 0.75, 0.25, 0.75]
+    );
+
+    let state = BlendState {
+        src_rgb: BlendFactor::ConstantColor,
+        dst_rgb: BlendFactor::Zero,
+        equation_rgb: BlendEquation::ReverseSubtract,
+        constant: [0.5, 0.5, 0.5, 0.5],
+        ..BlendState::ALPHA
+    };
+    assert_eq!(state.apply([1.0, 1.0, 1.0, 1.0], dst)[0], 0.0);
+    assert_eq!(
+        (to_u8(1.0), to_u8(0.5), to_u8(-1.0), to_u8(2.0)),
+        (255, 128, 0, 255)
+    );
+}
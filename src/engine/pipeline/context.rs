@@ -1,168 +1,1770 @@
+use super::bin::*;
 use super::raster::*;
-use crate::engine::base::*;
-use crate::engine::frame::*;
-use crate::engine::program::{Program, ShaderData};
+use crate::engine::*;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
 
 pub struct Context {
-    pub near: f64,
-    pub far: f64,
     pub current_program: Program,
-    pub current_buffers: Vec<Vec<Vec4>>,
+    pub current_buffers: Vec<VertexBuffer>,
     pub current_frame: Frame,
+    pub depth: DepthState,
+    pub blend: BlendState,
+    pub stencil: StencilState,
+    pub raster: RasterState,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 struct Vertex {
+    /**
+     * the position written by the vertex shader, before the perspective divide
+     */
+    pub clip: Vec4,
     pub coord: Vec2,
+    /**
+     * the varying of this vertex
+     */
     pub index: usize,
+    /**
+     * the varying flat values come from, the last vertex of the primitive before clipping
+     */
+    pub provoking: usize,
     pub depth: f64,
+    /**
+     * 1 / w of the clip position, barycentrics weighted by it interpolate in eye space
+     */
+    pub inv_w: f64,
 }
 
-impl<'a> Context{
+impl Vertex {
+    fn new(clip: Vec4, index: usize) -> Vertex {
+        Vertex {
+            clip,
+            coord: Vec2::ORIGIN,
+            index,
+            provoking: index,
+            depth: 0.0,
+            inv_w: 0.0,
+        }
+    }
+}
+
+/**
+ * the clip volume -w <= x, y, z <= w as planes, a vertex is inside when `plane * clip >= 0`
+ */
+const CLIP_PLANES: [[f64; 4]; 6] = [
+    [1.0, 0.0, 0.0, 1.0],
+    [-1.0, 0.0, 0.0, 1.0],
+    [0.0, 1.0, 0.0, 1.0],
+    [0.0, -1.0, 0.0, 1.0],
+    [0.0, 0.0, 1.0, 1.0],
+    [0.0, 0.0, -1.0, 1.0],
+];
+
+impl Context {
+    /**
+     * draws with `program` into `frame`, no buffers are bound and every state is the default
+     */
+    pub fn new(program: Program, frame: Frame) -> Context {
+        Context {
+            current_program: program,
+            current_buffers: vec![],
+            current_frame: frame,
+            depth: DepthState::default(),
+            blend: BlendState::default(),
+            stencil: StencilState::default(),
+            raster: RasterState::default(),
+        }
+    }
     fn default_color() -> Vec4 {
         Vec4::new(0.0, 0.0, 0.0, 1.0)
     }
-    pub fn draw_triangles(&'a mut self, vertex_buffer_index: usize) {
-        let vertices = self
-            .current_buffers
-            .get(vertex_buffer_index)
-            .unwrap()
-            .clone();
-        self.projection(vertices)
+    /**
+     * clears the colors and resets the depth and stencil buffers to their clear values.
+     * like the other clears it stays inside `raster.scissor`
+     */
+    pub fn clear(&mut self) {
+        self.clear_scissored(Frame::clear);
+        self.clear_depth();
+        self.clear_stencil();
+    }
+    /**
+     * resets the depth buffer to `depth.clear_value`, colors and stencil are kept
+     */
+    pub fn clear_depth(&mut self) {
+        let depth = self.depth.clear_value;
+        self.clear_scissored(|frame| frame.clear_depth(depth));
+    }
+    /**
+     * resets the stencil buffer to `stencil.clear_value`, between the passes of an outline for example
+     */
+    pub fn clear_stencil(&mut self) {
+        let stencil = self.stencil.clear_value;
+        self.clear_scissored(|frame| frame.clear_stencil(stencil));
+    }
+    fn clear_scissored<F: FnOnce(&mut Frame)>(&mut self, clear: F) {
+        if self.raster.scissor.is_none() {
+            return clear(&mut self.current_frame);
+        }
+        let (x, y) = self.scissor_ranges();
+        let mut region = self.current_frame.region(x.clone(), y.clone());
+        clear(&mut region);
+        self.current_frame.set_region((x.start, y.start), &region);
+    }
+    /**
+     * the columns and rows fragments may be written to
+     */
+    fn scissor_ranges(&self) -> (Range<usize>, Range<usize>) {
+        let (width, height) = (self.current_frame.width, self.current_frame.height);
+        match self.raster.scissor {
+            Some(scissor) => scissor.ranges(width, height),
+            None => (0..width, 0..height),
+        }
+    }
+    /**
+     * the position of `coord` in the planes of the frame, None outside of the frame or the scissor
+     */
+    fn scissored_index(&self, coord: &(usize, usize)) -> Option<usize> {
+        let (x, y) = self.scissor_ranges();
+        if x.contains(&coord.0) && y.contains(&coord.1) {
+            self.current_frame.index(coord)
+        } else {
+            None
+        }
+    }
+    /**
+     * draws into `frame` from now on and returns the frame drawn into so far,
+     * to read it as a texture in a later pass or to bind it again
+     */
+    pub fn bind_frame(&mut self, frame: Frame) -> Frame {
+        std::mem::replace(&mut self.current_frame, frame)
+    }
+    pub fn draw_triangles(&mut self, vertex_buffer_index: usize) {
+        self.draw_arrays(Topology::Triangles, vertex_buffer_index)
+    }
+    /**
+     * draws the vertex buffer from the first vertex to the last with `current_program`
+     */
+    pub fn draw_arrays(&mut self, mode: Topology, vertex_buffer_index: usize) {
+        let count = self.current_buffers.get(vertex_buffer_index).unwrap().len();
+        self.draw_program(mode, vertex_buffer_index, (0..count).collect())
+    }
+    /**
+     * draws the vertices of the vertex buffer in the order of `indices` with `current_program`.
+     * panics when an index is past the end of the vertex buffer
+     */
+    pub fn draw_elements(&mut self, mode: Topology, vertex_buffer_index: usize, indices: &Indices) {
+        self.draw_program(mode, vertex_buffer_index, indices.to_vec())
     }
-    fn projection(&'a mut self, vertices: Vec<Vec4>) {
-        let current_uniform_values = self
+    fn draw_program(&mut self, mode: Topology, vertex_buffer_index: usize, indices: Vec<usize>) {
+        let current_uniform_values: Vec<ShaderData> = self
             .current_program
             .uniforms
             .iter()
             .map(|unif| unif.value.clone())
             .collect();
 
+        let positions = self.current_buffers.get(vertex_buffer_index).unwrap();
+        for attr in self.current_program.attributes.iter() {
+            let buffer = self.current_buffers.get(attr.index).unwrap();
+            if buffer.len() < positions.len() {
+                panic!(
+                    "attribute {} has {} vertices, the position buffer has {}",
+                    attr.name,
+                    buffer.len(),
+                    positions.len()
+                );
+            }
+        }
+        let vertices: Vec<(Vec4, Vec<ShaderData>)> = (0..positions.len())
+            .map(|vertex_index| {
+                let vertex_attribute_values = self
+                    .current_program
+                    .attributes
+                    .iter()
+                    .map(|attr| {
+                        let buffer = self.current_buffers.get(attr.index).unwrap();
+                        buffer.read(vertex_index, attr.layout.as_ref().unwrap_or(&buffer.layout))
+                    })
+                    .collect();
+                (
+                    positions.read_position(vertex_index),
+                    vertex_attribute_values,
+                )
+            })
+            .collect();
+
+        // the program is a shader like any other, it only has to be out of self while the frame is written
+        let program = std::mem::take(&mut self.current_program);
+        self.run(&program, &current_uniform_values, &vertices, mode, &indices);
+        self.current_program = program;
+    }
+    /**
+     * draws `vertices` with a typed shader, in the order of `indices` when there are some.
+     * panics when an index is past the end of `vertices`
+     */
+    pub fn draw<S: Shader>(
+        &mut self,
+        shader: &S,
+        uniforms: &S::Uniforms,
+        vertices: &[S::Vertex],
+        mode: Topology,
+        indices: Option<&Indices>,
+    ) {
+        let indices = match indices {
+            Some(indices) => indices.to_vec(),
+            None => (0..vertices.len()).collect(),
+        };
+        self.run(shader, uniforms, vertices, mode, &indices);
+    }
+    fn run<S: Shader>(
+        &mut self,
+        shader: &S,
+        uniforms: &S::Uniforms,
+        vertices: &[S::Vertex],
+        mode: Topology,
+        indices: &[usize],
+    ) {
+        if let Some(index) = indices.iter().find(|&&index| index >= vertices.len()) {
+            panic!(
+                "index {} is out of range for {} vertices",
+                index,
+                vertices.len()
+            );
+        }
+        let (vertices, mut varyings) = self.projection(shader, uniforms, vertices, indices);
+        let mut total_vertices: Vec<Vertex> = mode
+            .assemble(vertices.len())
+            .into_iter()
+            .map(|i| vertices[i])
+            .collect();
+
+        match mode.primitive_size() {
+            1 => self.clip_points(&mut total_vertices),
+            2 => self.clip_lines(shader, &mut total_vertices, &mut varyings),
+            _ => self.clip_before_raster(shader, &mut total_vertices, &mut varyings),
+        }
+
+        let (width, height) = (self.current_frame.width, self.current_frame.height);
+        let viewport = self
+            .raster
+            .viewport
+            .unwrap_or_else(|| Viewport::full(width, height));
+        for vertex in total_vertices.iter_mut() {
+            let [x, y, z, w] = vertex.clip.value;
+            let (x, y) = viewport.to_pixel(x / w, y / w);
+            vertex.coord = Vec2::new(x, y);
+            vertex.depth = viewport.depth(self.depth.window_depth(z / w));
+            vertex.inv_w = 1.0 / w;
+        }
+
+        match mode.primitive_size() {
+            1 => self.raster_points(shader, uniforms, total_vertices, varyings),
+            2 => self.raster_lines(shader, uniforms, total_vertices, varyings),
+            _ => {
+                let triangles = self.setup_triangles(&total_vertices);
+                match self.raster.polygon_mode {
+                    PolygonMode::Fill => self.raster(shader, uniforms, &triangles, &varyings),
+                    PolygonMode::Line => {
+                        let edges = triangle_edges(&triangles);
+                        self.raster_lines(shader, uniforms, edges, varyings)
+                    }
+                    PolygonMode::Wireframe(color) => {
+                        self.raster(shader, uniforms, &triangles, &varyings);
+                        self.raster_wireframe(&triangles, color);
+                    }
+                }
+            }
+        }
+    }
+    /**
+     * runs the vertex shader for every index. vertices go through a post-transform cache keyed by
+     * their index, so one shared by several primitives is only shaded once.
+     * returns a vertex per index and the varyings they point to
+     */
+    fn projection<S: Shader>(
+        &self,
+        shader: &S,
+        uniforms: &S::Uniforms,
+        vertices: &[S::Vertex],
+        indices: &[usize],
+    ) -> (Vec<Vertex>, Vec<S::Varying>) {
+        let mut cache: Vec<Option<Vertex>> = vec![None; vertices.len()];
         let mut total_vertices: Vec<Vertex> = vec![];
-        // let mut i = 0;
-        let mut current_attribute_values: Vec<Vec<ShaderData>> = vec![];
+        let mut varyings = vec![];
 
-        for (vertex_index, vertex_coord) in vertices.into_iter().enumerate() {
-            let vertex_shader = &self.current_program.vertex_shader;
+        for &vertex_index in indices {
+            if let Some(vertex) = cache[vertex_index] {
+                total_vertices.push(vertex);
+                continue;
+            }
+            let (projected_vertices, varying) = shader.vertex(&vertices[vertex_index], uniforms);
+            varyings.push(varying);
 
-            let vertex_attribute_values = self
-                .current_program
-                .attributes
-                .iter()
-                .map(|attr| {
-                    // todo read buffer into different data structures.
-                    let v = self
-                        .current_buffers
-                        .get(attr.index)
-                        .unwrap()
-                        .get(vertex_index)
-                        .unwrap();
-                    ShaderData::Vec4(v.clone())
-                })
-                .collect();
-
-            let projected_vertices = vertex_shader(
-                &vertex_attribute_values,
-                &current_uniform_values,
-                vertex_coord.clone(),
-            );
+            let vertex = Vertex::new(projected_vertices, varyings.len() - 1);
+            cache[vertex_index] = Some(vertex);
+            total_vertices.push(vertex);
+        }
 
-            current_attribute_values.push(vertex_attribute_values);
+        (total_vertices, varyings)
+    }
 
-            total_vertices.push(Vertex {
-                coord: Vec2{
-                    value:[
-                        (projected_vertices.x() / projected_vertices.w() + 1.0) / 2.0 * self.current_frame.width as f64,
-                        (projected_vertices.y() / projected_vertices.w() + 1.0) / 2.0 * self.current_frame.height as f64,
-                    ]
-                },
-                index: vertex_index,
-                depth: projected_vertices.z() / projected_vertices.w(),
-            })
+    /**
+     * points are either inside the clip volume or dropped
+     */
+    fn clip_points(&self, total_vertices: &mut Vec<Vertex>) {
+        total_vertices.retain(|vertex| {
+            CLIP_PLANES
+                .iter()
+                .all(|plane| plane_distance(plane, &vertex.clip) >= 0.0)
+        });
+    }
+
+    /**
+     * cuts every line to the part inside the clip volume, lines outside of it are dropped
+     */
+    fn clip_lines<S: Shader>(
+        &self,
+        shader: &S,
+        total_vertices: &mut Vec<Vertex>,
+        varyings: &mut Vec<S::Varying>,
+    ) {
+        let mut clipped = vec![];
+        'line: for line in total_vertices.chunks_exact(2) {
+            let (mut t0, mut t1) = (0.0f64, 1.0f64);
+            for plane in CLIP_PLANES.iter() {
+                let d0 = plane_distance(plane, &line[0].clip);
+                let d1 = plane_distance(plane, &line[1].clip);
+                if d0 < 0.0 && d1 < 0.0 {
+                    continue 'line;
+                }
+                if d0 < 0.0 {
+                    t0 = t0.max(d0 / (d0 - d1));
+                } else if d1 < 0.0 {
+                    t1 = t1.min(d0 / (d0 - d1));
+                }
+            }
+            if t0 > t1 {
+                continue;
+            }
+            let provoking = line[1].index;
+            for (end, t) in [(0, t0), (1, t1)].iter() {
+                let mut vertex = line[*end];
+                if *t != *end as f64 {
+                    let varying = lerp_varying(shader, varyings, &line[0], &line[1], provoking, *t);
+                    varyings.push(varying);
+                    vertex =
+                        Vertex::new(lerp(&line[0].clip, &line[1].clip, *t), varyings.len() - 1);
+                }
+                vertex.provoking = provoking;
+                clipped.push(vertex);
+            }
         }
+        *total_vertices = clipped;
+    }
 
-        println!("Projection complete");
+    /**
+     * sutherland-hodgman clipping of every triangle against the six planes of the clip volume,
+     * the polygon left over is split into a fan of triangles again.
+     * vertices made on a plane get interpolated varyings appended to `varyings`
+     */
+    fn clip_before_raster<S: Shader>(
+        &self,
+        shader: &S,
+        total_vertices: &mut Vec<Vertex>,
+        varyings: &mut Vec<S::Varying>,
+    ) {
+        let mut clipped = vec![];
+        for triangle in total_vertices.chunks_exact(3) {
+            let outside = |vertex: &Vertex| {
+                CLIP_PLANES
+                    .iter()
+                    .any(|plane| plane_distance(plane, &vertex.clip) < 0.0)
+            };
+            if !triangle.iter().any(outside) {
+                clipped.extend_from_slice(triangle);
+                continue;
+            }
 
-        self.raster(total_vertices, current_attribute_values);
-        self.fragment(&current_uniform_values);
+            // flat varyings come from the last vertex, which clipping may cut off
+            let provoking = triangle[2].index;
+            let mut polygon = triangle.to_vec();
+            for plane in CLIP_PLANES.iter() {
+                if polygon.is_empty() {
+                    break;
+                }
+                let mut output = vec![];
+                let mut previous = polygon[polygon.len() - 1];
+                for current in polygon.iter() {
+                    let previous_distance = plane_distance(plane, &previous.clip);
+                    let current_distance = plane_distance(plane, &current.clip);
+                    if (previous_distance >= 0.0) != (current_distance >= 0.0) {
+                        let t = previous_distance / (previous_distance - current_distance);
+                        let varying =
+                            lerp_varying(shader, varyings, &previous, current, provoking, t);
+                        varyings.push(varying);
+                        output.push(Vertex::new(
+                            lerp(&previous.clip, &current.clip, t),
+                            varyings.len() - 1,
+                        ));
+                    }
+                    if current_distance >= 0.0 {
+                        output.push(*current);
+                    }
+                    previous = *current;
+                }
+                polygon = output;
+            }
+
+            for vertex in polygon.iter_mut() {
+                vertex.provoking = provoking;
+            }
+            for i in 1..polygon.len().saturating_sub(1) {
+                clipped.extend_from_slice(&[polygon[0], polygon[i], polygon[i + 1]]);
+            }
+        }
+        *total_vertices = clipped;
     }
 
-    fn clip_before_raster(&self, total_vertices: &mut Vec<Vertex>, ){
-        
+    /**
+     * culls back faces unless `raster.cull_back_faces` is off, the triangles left keep their polygon offset
+     */
+    fn setup_triangles(&self, total_vertices: &[Vertex]) -> Vec<([Vertex; 3], f64)> {
+        let mut triangles = vec![];
+        for triangle in total_vertices.chunks_exact(3) {
+            let (a, b, c) = (&triangle[0], &triangle[1], &triangle[2]);
+            if front_facing(&[*a, *b, *c]) || !self.raster.cull_back_faces {
+                let offset = self.depth.offset(
+                    [
+                        (a.coord.x(), a.coord.y()),
+                        (b.coord.x(), b.coord.y()),
+                        (c.coord.x(), c.coord.y()),
+                    ],
+                    [a.depth, b.depth, c.depth],
+                );
+                triangles.push(([*a, *b, *c], offset));
+            }
+        }
+        triangles
     }
+    /**
+     * sort-middle rasterization: the triangles are binned into tiles of the frame, then the bins are
+     * rasterized and shaded on `raster.threads` workers. a bin draws its triangles in order,
+     * so depth tests and blending give the same frame as drawing them one by one
+     */
+    fn raster<S: Shader>(
+        &mut self,
+        shader: &S,
+        uniforms: &S::Uniforms,
+        triangles: &[([Vertex; 3], f64)],
+        varyings: &[S::Varying],
+    ) {
+        let depth_state = self.depth;
+        let blend_state = self.blend;
+        let stencil_state = self.stencil;
+
+        let coords: Vec<[(f64, f64); 3]> = triangles
+            .iter()
+            .map(|(triangle, _)| triangle.map(|v| (v.coord.x(), v.coord.y())))
+            .collect();
+        let (width, height) = (self.current_frame.width, self.current_frame.height);
+        let bins = bin_triangles(&coords, width, height, self.raster.bin_size);
+
+        // without blending or discards only the last fragment to pass the depth test is seen,
+        // the visibility buffer keeps its triangle and the pixel is shaded once when the bin is done
+        let samples = self.current_frame.samples;
+        let deferred =
+            samples == 1 && !blend_state.enabled && !blend_state.alpha_to_coverage && !S::DISCARDS;
+        self.current_frame.clear_visibility();
+
+        let (scissor_x, scissor_y) = self.scissor_ranges();
+        let frame = &self.current_frame;
+        let next_bin = AtomicUsize::new(0);
+        let (sender, receiver) = mpsc::channel();
+        let mut shaded = vec![];
+        std::thread::scope(|scope| {
+            for _ in 0..self.raster.threads.clamp(1, bins.len().max(1)) {
+                let sender = sender.clone();
+                let (bins, next_bin) = (&bins, &next_bin);
+                let (scissor_x, scissor_y) = (&scissor_x, &scissor_y);
+                scope.spawn(move || {
+                    while let Some(bin) = bins.get(next_bin.fetch_add(1, Ordering::Relaxed)) {
+                        let x = bin.x.start.max(scissor_x.start)..bin.x.end.min(scissor_x.end);
+                        let y = bin.y.start.max(scissor_y.start)..bin.y.end.min(scissor_y.end);
+                        if x.is_empty() || y.is_empty() {
+                            continue;
+                        }
+                        let mut target = frame.region(bin.x.clone(), bin.y.clone());
+                        let bin_width = target.width;
+                        for &i in bin.triangles.iter() {
+                            let (triangle, offset) = &triangles[i];
+                            let [a, b, c] = triangle;
+                            let slope = depth_slope(triangle);
+                            let points = raster_triangle_samples(
+                                &a.coord,
+                                &b.coord,
+                                &c.coord,
+                                x.clone(),
+                                y.clone(),
+                                sample_pattern(samples),
+                            );
+                            for ((x, y), mask, (alpha, beta, gamma)) in points {
+                                // window depth is affine in screen space, so unlike the varyings it is interpolated as is
+                                let depth =
+                                    a.depth * alpha + b.depth * beta + c.depth * gamma + offset;
+                                let fragment = Fragment {
+                                    index: (y - bin.y.start) * bin_width + x - bin.x.start,
+                                    coord: Vec2::new(x as f64, y as f64),
+                                    depth,
+                                    slope,
+                                    mask,
+                                    front_facing: front_facing(triangle),
+                                };
+                                if !deferred {
+                                    shade_fragment(
+                                        shader,
+                                        uniforms,
+                                        (&depth_state, &stencil_state, &blend_state),
+                                        &mut target,
+                                        &fragment,
+                                        || {
+                                            let weights = [alpha, beta, gamma];
+                                            triangle_varying(shader, varyings, triangle, weights)
+                                        },
+                                    );
+                                } else if fragment_tests(
+                                    (&depth_state, &stencil_state),
+                                    &mut target,
+                                    &fragment,
+                                ) != 0
+                                {
+                                    write_sample(
+                                        (&depth_state, &stencil_state),
+                                        &mut target,
+                                        &fragment,
+                                        0,
+                                    );
+                                    target.visibility[fragment.index] =
+                                        Visibility::new(i, alpha, beta);
+                                }
+                            }
+                        }
+                        if deferred {
+                            for index in 0..target.visibility.len() {
+                                let visibility = target.visibility[index];
+                                if visibility.is_none() {
+                                    continue;
+                                }
+                                let triangle = &triangles[visibility.triangle as usize].0;
+                                let varying = triangle_varying(
+                                    shader,
+                                    varyings,
+                                    triangle,
+                                    visibility.weights(),
+                                );
+                                let (x, y) = (index % target.width, index / target.width);
+                                let coord =
+                                    Vec2::new((bin.x.start + x) as f64, (bin.y.start + y) as f64);
+                                if let Some((color, outputs)) =
+                                    shader.fragment_outputs(&varying, uniforms, coord)
+                                {
+                                    blend_fragment(&blend_state, &mut target, index, color);
+                                    write_attachments(&mut target, index, &outputs);
+                                }
+                            }
+                        }
+                        if sender.send((bin, target)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            // only the workers hold senders now, the loop ends when all of them are done
+            drop(sender);
+            shaded.extend(receiver.iter());
+        });
 
-    fn raster(
+        // bins do not overlap, the order they finished in does not matter
+        for (bin, target) in shaded {
+            self.current_frame
+                .set_region((bin.x.start, bin.y.start), &target);
+        }
+    }
+    fn raster_lines<S: Shader>(
         &mut self,
+        shader: &S,
+        uniforms: &S::Uniforms,
         total_vertices: Vec<Vertex>,
-        current_attribute_values: Vec<Vec<ShaderData>>,
+        varyings: Vec<S::Varying>,
     ) {
-        let mut i = 0;
-
-        let z_buffer_unit = 256.0 / (self.far - self.near);
-
-        while i + 2 < total_vertices.len() {
-            let a = total_vertices.get(i).unwrap();
-            let b = total_vertices.get(i + 1).unwrap();
-            let c = total_vertices.get(i + 2).unwrap();
-            let ab_cross_bc = Vec2::cross(&(&b.coord - &a.coord), &(&c.coord - &b.coord));
-            if ab_cross_bc < 0.0 {
-                let points = raster_triangle(&a.coord, &b.coord, &c.coord);
-                let attrs = [
-                    current_attribute_values.get(a.index).unwrap(),
-                    current_attribute_values.get(b.index).unwrap(),
-                    current_attribute_values.get(c.index).unwrap(),
-                ];
-                for (coord, (alpha, beta, gamma)) in points {
-                    let depth = a.depth * alpha + b.depth * beta + c.depth * gamma;
-                    let z = ((depth - self.near) * z_buffer_unit).round() as u8;
-                    let varyings = interpolate_attribute(attrs, alpha, beta, gamma);
-
-                    if let Some(prev_pixel) = self.current_frame.get_mut(&coord) {
-                        let prev_pixel_z = { prev_pixel.z };
-                        if prev_pixel_z < z {
-                            prev_pixel.varying = Some(varyings);
+        for line in total_vertices.chunks_exact(2) {
+            let (a, b) = (&line[0], &line[1]);
+            let attrs = [&varyings[a.index], &varyings[b.index], &varyings[b.index]];
+            let provoking = &varyings[b.provoking];
+            let inv_w = [a.inv_w, b.inv_w, b.inv_w];
+            let points = raster_wide_line(
+                &Vec4::new(a.coord.x(), a.coord.y(), 0.0, 1.0),
+                &Vec4::new(b.coord.x(), b.coord.y(), 0.0, 1.0),
+                self.raster.line_width,
+            );
+            for (coord, t) in points {
+                let depth = a.depth * (1.0 - t) + b.depth * t;
+                self.write_fragment(shader, uniforms, &coord, depth, || {
+                    let weights = Barycentric::new([1.0 - t, t, 0.0], inv_w);
+                    shader.interpolate(attrs, provoking, &weights)
+                });
+            }
+        }
+    }
+    fn raster_points<S: Shader>(
+        &mut self,
+        shader: &S,
+        uniforms: &S::Uniforms,
+        total_vertices: Vec<Vertex>,
+        varyings: Vec<S::Varying>,
+    ) {
+        for point in total_vertices.iter() {
+            for coord in raster_point(&point.coord, self.raster.point_size) {
+                self.write_fragment(shader, uniforms, &coord, point.depth, || {
+                    varyings[point.index].clone()
+                });
+            }
+        }
+    }
+    /**
+     * draws the edges of filled triangles in `color`. the edges are pulled towards the viewer
+     * by a polygon offset so they win the depth test against their own triangles, depth is not written
+     */
+    fn raster_wireframe(&mut self, triangles: &[([Vertex; 3], f64)], color: [f64; 4]) {
+        let sign = if self.depth.reversed { 1.0 } else { -1.0 };
+        let pull = DepthState {
+            polygon_offset: Some(PolygonOffset {
+                factor: sign,
+                units: sign * 4.0,
+            }),
+            ..self.depth
+        };
+        let overlay = DepthState {
+            write: false,
+            ..self.depth
+        };
+        for (triangle, offset) in triangles {
+            let [a, b, c] = triangle;
+            let offset = offset
+                + pull.offset(
+                    [
+                        (a.coord.x(), a.coord.y()),
+                        (b.coord.x(), b.coord.y()),
+                        (c.coord.x(), c.coord.y()),
+                    ],
+                    [a.depth, b.depth, c.depth],
+                );
+            for (from, to) in [(a, b), (b, c), (c, a)].iter() {
+                let points = raster_wide_line(
+                    &Vec4::new(from.coord.x(), from.coord.y(), 0.0, 1.0),
+                    &Vec4::new(to.coord.x(), to.coord.y(), 0.0, 1.0),
+                    self.raster.line_width,
+                );
+                for (coord, t) in points {
+                    if let Some(index) = self.scissored_index(&coord) {
+                        let fragment = Fragment::pixel(
+                            index,
+                            coord,
+                            from.depth * (1.0 - t) + to.depth * t + offset,
+                        );
+                        let frame = &mut self.current_frame;
+                        let tests = (&overlay, &self.stencil);
+                        let mask = fragment_tests(tests, frame, &fragment);
+                        for sample in samples_in(mask, frame.samples) {
+                            write_sample(tests, frame, &fragment, sample);
+                            let color = Vec4 { value: color };
+                            blend_fragment(
+                                &self.blend,
+                                frame,
+                                index * frame.samples + sample,
+                                color,
+                            );
                         }
                     }
                 }
             }
-            println!("{} vertices remains...", total_vertices.len() - i);
-            i += 3;
         }
+    }
+    fn write_fragment<S: Shader, F: FnOnce() -> S::Varying>(
+        &mut self,
+        shader: &S,
+        uniforms: &S::Uniforms,
+        coord: &(usize, usize),
+        depth: f64,
+        varyings: F,
+    ) {
+        if let Some(index) = self.scissored_index(coord) {
+            let fragment = Fragment::pixel(index, *coord, depth);
+            let states = (&self.depth, &self.stencil, &self.blend);
+            shade_fragment(
+                shader,
+                uniforms,
+                states,
+                &mut self.current_frame,
+                &fragment,
+                varyings,
+            );
+        }
+    }
+}
+
+/**
+ * the edges of `triangles` as lines, flat varyings still come from the provoking vertex of the triangle
+ */
+fn triangle_edges(triangles: &[([Vertex; 3], f64)]) -> Vec<Vertex> {
+    let mut edges = vec![];
+    for ([a, b, c], _) in triangles {
+        for vertex in [a, b, b, c, c, a].iter() {
+            edges.push(Vertex {
+                provoking: c.provoking,
+                ..**vertex
+            });
+        }
+    }
+    edges
+}
 
-        println!("Raster complete");
+/**
+ * the samples of a primitive in one pixel, `index` is the pixel in the planes of the target.
+ * the depth is that of the pixel center, `slope` moves it to the samples in `mask`
+ */
+struct Fragment {
+    index: usize,
+    coord: Vec2,
+    depth: f64,
+    slope: (f64, f64),
+    mask: u32,
+    /**
+     * picks the stencil face, lines and points are front facing
+     */
+    front_facing: bool,
+}
+
+impl Fragment {
+    /**
+     * a fragment of a line or point, it covers every sample of its pixel at the same depth
+     */
+    fn pixel(index: usize, coord: (usize, usize), depth: f64) -> Fragment {
+        Fragment {
+            index,
+            coord: Vec2::new(coord.0 as f64, coord.1 as f64),
+            depth,
+            slope: (0.0, 0.0),
+            mask: u32::MAX,
+            front_facing: true,
+        }
     }
-    fn fragment(&mut self, current_uniform_values: &Vec<ShaderData>) {
-        for mut pixel in self.current_frame.buffer.iter_mut() {
-            if let Some(varyings) = &pixel.varying {
-                let fragment_shader = &self.current_program.fragment_shader;
-                let gl_frag_color =
-                    fragment_shader(&varyings, current_uniform_values, pixel.coord.clone());
-                pixel.color = (
-                    (gl_frag_color.x() * 256.0).round() as u8,
-                    (gl_frag_color.y() * 256.0).round() as u8,
-                    (gl_frag_color.z() * 256.0).round() as u8,
-                    (gl_frag_color.w() * 256.0).round() as u8,
-                );
+}
+
+/**
+ * pixel y points down, a positive cross product is clockwise in ndc
+ */
+fn front_facing(triangle: &[Vertex; 3]) -> bool {
+    let [a, b, c] = triangle;
+    Vec2::cross(&(&b.coord - &a.coord), &(&c.coord - &b.coord)) > 0.0
+}
+
+/**
+ * dz/dx and dz/dy of the window depth of `triangle`
+ */
+fn depth_slope(triangle: &[Vertex; 3]) -> (f64, f64) {
+    let [a, b, c] = triangle;
+    let ab = (b.coord.x() - a.coord.x(), b.coord.y() - a.coord.y());
+    let ac = (c.coord.x() - a.coord.x(), c.coord.y() - a.coord.y());
+    let area = ab.0 * ac.1 - ac.0 * ab.1;
+    if area == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (dz_b, dz_c) = (b.depth - a.depth, c.depth - a.depth);
+    (
+        (dz_b * ac.1 - dz_c * ab.1) / area,
+        (dz_c * ab.0 - dz_b * ac.0) / area,
+    )
+}
+
+/**
+ * the depth of `fragment` at sample `sample` of a target with `samples` samples per pixel
+ */
+fn sample_depth(fragment: &Fragment, samples: usize, sample: usize) -> f32 {
+    let (dx, dy) = sample_pattern(samples)[sample];
+    let depth = fragment.depth + fragment.slope.0 * dx + fragment.slope.1 * dy;
+    depth.clamp(0.0, 1.0) as f32
+}
+
+/**
+ * the samples set in `mask`, out of `samples`
+ */
+fn samples_in(mask: u32, samples: usize) -> impl Iterator<Item = usize> {
+    (0..samples).filter(move |sample| mask >> sample & 1 == 1)
+}
+
+/**
+ * the samples of `fragment` that pass the stencil and depth tests of `target`.
+ * the samples that fail get the fail or depth fail op of their stencil face
+ */
+fn fragment_tests(
+    (depth_state, stencil_state): (&DepthState, &StencilState),
+    target: &mut Frame,
+    fragment: &Fragment,
+) -> u32 {
+    let face = stencil_state.face(fragment.front_facing);
+    let samples = target.samples;
+    samples_in(fragment.mask, samples).fold(0, |mask, sample| {
+        let index = fragment.index * samples + sample;
+        let stored = target.stencil[index];
+        if let Some(face) = face.filter(|face| !face.test(stored)) {
+            target.stencil[index] = face.update(face.fail, stored);
+            return mask;
+        }
+        let z = sample_depth(fragment, samples, sample);
+        if depth_state.func.test(z, target.depth[index]) {
+            return mask | 1 << sample;
+        }
+        if let Some(face) = face {
+            target.stencil[index] = face.update(face.depth_fail, stored);
+        }
+        mask
+    })
+}
+
+/**
+ * writes the depth of a sample that passed `fragment_tests` and applies the pass op of its stencil face
+ */
+fn write_sample(
+    (depth_state, stencil_state): (&DepthState, &StencilState),
+    target: &mut Frame,
+    fragment: &Fragment,
+    sample: usize,
+) {
+    let index = fragment.index * target.samples + sample;
+    if depth_state.write {
+        target.depth[index] = sample_depth(fragment, target.samples, sample);
+    }
+    if let Some(face) = stencil_state.face(fragment.front_facing) {
+        target.stencil[index] = face.update(face.pass, target.stencil[index]);
+    }
+}
+
+/**
+ * the first round(alpha * samples) samples, dithering is left out so equal alphas give equal masks
+ */
+fn coverage_mask(alpha: f64, samples: usize) -> u32 {
+    let covered = (alpha.clamp(0.0, 1.0) * samples as f64).round() as u32;
+    if covered >= 32 {
+        u32::MAX
+    } else {
+        (1 << covered) - 1
+    }
+}
+
+/**
+ * stencil and depth tests a fragment, shades it once when any of its samples pass and blends it into them.
+ * `varyings` is only called for fragments that pass, discarded fragments leave the depth and
+ * the stencil of their passing samples as they were
+ */
+fn shade_fragment<S: Shader, F: FnOnce() -> S::Varying>(
+    shader: &S,
+    uniforms: &S::Uniforms,
+    (depth_state, stencil_state, blend_state): (&DepthState, &StencilState, &BlendState),
+    target: &mut Frame,
+    fragment: &Fragment,
+    varyings: F,
+) {
+    let mut mask = fragment_tests((depth_state, stencil_state), target, fragment);
+    if mask == 0 {
+        return;
+    }
+    // fragments are shaded in draw order, blending needs the color of the ones before
+    let varying = varyings();
+    let (gl_frag_color, outputs) = match shader.fragment_outputs(&varying, uniforms, fragment.coord)
+    {
+        Some(outputs) => outputs,
+        None => return,
+    };
+    if blend_state.alpha_to_coverage {
+        mask &= coverage_mask(gl_frag_color.w(), target.samples);
+    }
+    for sample in samples_in(mask, target.samples) {
+        write_sample((depth_state, stencil_state), target, fragment, sample);
+        let index = fragment.index * target.samples + sample;
+        blend_fragment(blend_state, target, index, gl_frag_color);
+    }
+    if mask != 0 {
+        write_attachments(target, fragment.index, &outputs);
+    }
+}
+
+/**
+ * stores the extra outputs of a fragment in the attachments of pixel `index`.
+ * attachments have one value per pixel and are not blended
+ */
+fn write_attachments(target: &mut Frame, index: usize, outputs: &[Vec4]) {
+    for (plane, output) in target.attachments.iter_mut().zip(outputs.iter()) {
+        plane[index] = output.value.map(|channel| channel as f32);
+    }
+}
+
+/**
+ * writes `gl_frag_color` to sample `index` of the color plane through the blend state and its write mask
+ */
+fn blend_fragment(blend_state: &BlendState, target: &mut Frame, index: usize, gl_frag_color: Vec4) {
+    let (r, g, b, a) = target.color[index];
+    let dst = [r, g, b, a].map(|channel| f64::from(channel) / 255.0);
+    let color = blend_state.apply(gl_frag_color.value, dst).map(to_u8);
+    target.color[index] = (color[0], color[1], color[2], color[3]);
+}
+
+/**
+ * the varying of `triangle` at screen space `weights`
+ */
+fn triangle_varying<S: Shader>(
+    shader: &S,
+    varyings: &[S::Varying],
+    triangle: &[Vertex; 3],
+    weights: [f64; 3],
+) -> S::Varying {
+    let [a, b, c] = triangle;
+    let weights = Barycentric::new(weights, [a.inv_w, b.inv_w, c.inv_w]);
+    shader.interpolate(
+        [&varyings[a.index], &varyings[b.index], &varyings[c.index]],
+        &varyings[c.provoking],
+        &weights,
+    )
+}
+
+/**
+ * the varying a fraction `t` of the way from `from` to `to`, flat values are those of `provoking`
+ */
+fn lerp_varying<S: Shader>(
+    shader: &S,
+    varyings: &[S::Varying],
+    from: &Vertex,
+    to: &Vertex,
+    provoking: usize,
+    t: f64,
+) -> S::Varying {
+    let (from, to) = (&varyings[from.index], &varyings[to.index]);
+    shader.interpolate(
+        [from, to, to],
+        &varyings[provoking],
+        &Barycentric::linear([1.0 - t, t, 0.0]),
+    )
+}
+
+fn plane_distance(plane: &[f64; 4], clip: &Vec4) -> f64 {
+    (0..4).map(|i| plane[i] * clip.value[i]).sum()
+}
+
+/**
+ * componentwise, the w of clip coordinates has to be interpolated like x, y and z
+ */
+fn lerp(a: &Vec4, b: &Vec4, t: f64) -> Vec4 {
+    let mut value = [0.0; 4];
+    for (i, v) in value.iter_mut().enumerate() {
+        *v = a.value[i] + (b.value[i] - a.value[i]) * t;
+    }
+    Vec4 { value }
+}
+
+#[test]
+fn test_clip_before_raster() {
+    let context = Context::new(Program::default(), Frame::new(4, 4));
+    let vertex = |index: usize, clip: Vec4| Vertex::new(clip, index);
+    let mut attribute_values = vec![
+        vec![ShaderData::Float(0.0)],
+        vec![ShaderData::Float(1.0)],
+        vec![ShaderData::Float(2.0)],
+        vec![ShaderData::Float(0.0)],
+        vec![ShaderData::Float(0.0)],
+        vec![ShaderData::Float(0.0)],
+    ];
+    // the third vertex is in front of the near plane, the second triangle is left of the screen
+    let mut vertices = vec![
+        vertex(0, Vec4::new(-0.5, -0.5, 0.0, 1.0)),
+        vertex(1, Vec4::new(0.5, -0.5, 0.0, 1.0)),
+        vertex(2, Vec4::new(0.0, 0.0, -3.0, 1.0)),
+        vertex(3, Vec4::new(-3.0, 0.0, 0.0, 1.0)),
+        vertex(4, Vec4::new(-2.0, 0.0, 0.0, 1.0)),
+        vertex(5, Vec4::new(-3.0, 1.0, 0.0, 1.0)),
+    ];
+    context.clip_before_raster(&Program::default(), &mut vertices, &mut attribute_values);
+
+    // cutting a corner off leaves a quad, two triangles
+    assert_eq!(vertices.len(), 6);
+    for vertex in vertices.iter() {
+        for plane in CLIP_PLANES.iter() {
+            assert!(
+                plane_distance(plane, &vertex.clip) > -1e-9,
+                "{:?} is outside",
+                vertex
+            );
+        }
+        let [_, _, z, w] = vertex.clip.value;
+        if vertex.index >= 6 {
+            // on the near plane z = -w, a third of the way to the third vertex
+            assert!((z + w).abs() < 1e-9);
+            match attribute_values[vertex.index][0] {
+                ShaderData::Float(v) => {
+                    assert!((v - 2.0 / 3.0).abs() < 1e-9 || (v - 4.0 / 3.0).abs() < 1e-9)
+                }
+                _ => panic!("the attribute changed its type"),
             }
         }
-        println!("Fragment complete");
     }
-    fn blend(&mut self) {}
+    assert_eq!(attribute_values.len(), 8);
+}
+
+#[test]
+fn test_depth_test() {
+    use crate::engine::program::{Attribute, Interpolation};
+
+    let triangle = |z: f64| {
+        vec![
+            Vec4::new(-1.0, -1.0, z, 1.0),
+            Vec4::new(0.0, 1.0, z, 1.0),
+            Vec4::new(1.0, -1.0, z, 1.0),
+        ]
+    };
+    // the near triangle is drawn first, the far one has to fail the test after it
+    let positions = [triangle(-0.5), triangle(0.5)].concat();
+    let colors = [
+        vec![Vec4::new(0.25, 0.0, 0.0, 1.0); 3],
+        vec![Vec4::new(0.5, 0.0, 0.0, 1.0); 3],
+    ]
+    .concat();
+    let draw = |depth: DepthState| {
+        let mut context = Context {
+            current_buffers: vec![positions.clone().into(), colors.clone().into()],
+            depth,
+            ..Context::new(
+                Program {
+                    vertex_shader: Box::new(|_, _, position| position),
+                    fragment_shader: Box::new(|attributes, _, _| match &attributes[0] {
+                        ShaderData::Vec4(color) => *color,
+                        _ => Context::default_color(),
+                    }),
+                    outputs: vec![],
+                    attributes: vec![Attribute {
+                        index: 1,
+                        name: "color".to_string(),
+                        interpolation: Interpolation::Flat,
+                        layout: None,
+                    }],
+                    uniforms: vec![],
+                },
+                Frame::new(8, 8),
+            )
+        };
+        context.clear();
+        context.draw_triangles(0);
+        let frame = &context.current_frame;
+        (
+            frame.get_color(&(4, 4)).unwrap().0,
+            frame.get_depth(&(4, 4)).unwrap(),
+        )
+    };
+
+    assert_eq!(draw(DepthState::default()), (64, 0.25));
+    assert_eq!(draw(DepthState::REVERSED), (64, 0.75));
+    let greater = DepthState {
+        func: CompareFunc::Greater,
+        clear_value: 0.0,
+        ..DepthState::default()
+    };
+    assert_eq!(draw(greater), (128, 0.75));
+    let no_write = DepthState {
+        write: false,
+        ..DepthState::default()
+    };
+    // without writes both pass against the cleared buffer, the last one wins
+    assert_eq!(draw(no_write), (128, 1.0));
 }
 
-fn interpolate_attribute<'a, 'b>(
-    attrs: [&'b Vec<ShaderData>; 3],
-    alpha: f64,
-    beta: f64,
-    gamma: f64,
-) -> Vec<ShaderData> {
-    let attr_len = attrs[0].len();
-    let mut res = vec![];
-    for i in 0..attr_len {
-        let a_attr = attrs[0].get(i).unwrap() * alpha;
-        let b_attr = attrs[1].get(i).unwrap() * beta;
-        let c_attr = attrs[2].get(i).unwrap() * gamma;
-        res.push(a_attr + b_attr + c_attr);
+#[test]
+fn test_draw_elements() {
+    use crate::engine::buffer::{VertexFormat, VertexLayout};
+    use crate::engine::program::{Attribute, Interpolation};
+    use std::sync::Arc;
+
+    let invocations = Arc::new(AtomicUsize::new(0));
+    let counter = invocations.clone();
+    let mut context = Context {
+        // a quad over the left half of the screen, corners clockwise from the bottom left
+        current_buffers: vec![
+            VertexBuffer::from_f32(&[-1.0, -1.0, -1.0, 1.0, 0.0, 1.0, 0.0, -1.0], 2),
+            VertexBuffer::new(
+                vec![255; 16],
+                4,
+                VertexLayout {
+                    offset: 0,
+                    format: VertexFormat::UNORM8X4,
+                },
+            ),
+        ],
+        depth: DepthState {
+            func: CompareFunc::Always,
+            ..DepthState::default()
+        },
+        ..Context::new(
+            Program {
+                vertex_shader: Box::new(move |_, _, position| {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    position
+                }),
+                fragment_shader: Box::new(|attributes, _, _| match &attributes[0] {
+                    ShaderData::Vec4(color) => *color,
+                    _ => Context::default_color(),
+                }),
+                outputs: vec![],
+                attributes: vec![Attribute {
+                    index: 1,
+                    name: "color".to_string(),
+                    interpolation: Interpolation::Smooth,
+                    layout: None,
+                }],
+                uniforms: vec![],
+            },
+            Frame::new(8, 8),
+        )
+    };
+    let covered = |context: &Context| {
+        let mut covered = vec![];
+        for y in 0..8 {
+            for x in 0..8 {
+                if context.current_frame.get_color(&(x, y)).unwrap().0 > 0 {
+                    covered.push((x, y));
+                }
+            }
+        }
+        covered
+    };
+
+    context.draw_elements(Topology::Triangles, 0, &Indices::U16(&[0, 1, 2, 0, 2, 3]));
+    assert_eq!(
+        invocations.load(Ordering::Relaxed),
+        4,
+        "shared vertices were shaded again"
+    );
+    let quad = covered(&context);
+    assert!(quad.iter().all(|(x, _)| *x <= 4));
+    assert!((0..8).all(|y| quad.contains(&(0, y)) && quad.contains(&(3, y))));
+
+    for (mode, indices) in [
+        (Topology::TriangleFan, vec![0u32, 1, 2, 3]),
+        (Topology::TriangleStrip, vec![0, 1, 3, 2]),
+    ]
+    .iter()
+    {
+        context.clear();
+        context.draw_elements(*mode, 0, &Indices::U32(indices));
+        assert_eq!(
+            covered(&context),
+            quad,
+            "{:?} differs from the triangle list",
+            mode
+        );
     }
-    res
+
+    context.clear();
+    context.draw_elements(Topology::LineStrip, 0, &Indices::U16(&[1, 2, 3]));
+    let lines = covered(&context);
+    assert!((0..4).all(|x| lines.contains(&(x, 0))), "{:?}", lines);
+    assert!((0..8).all(|y| lines.contains(&(4, y))), "{:?}", lines);
+
+    context.clear();
+    context.draw_elements(Topology::Points, 0, &Indices::U16(&[2]));
+    assert_eq!(covered(&context), vec![(4, 0)]);
+}
+
+#[test]
+#[should_panic(expected = "index 3 is out of range for 3 vertices")]
+fn test_draw_elements_out_of_range() {
+    let mut context = Context {
+        current_buffers: vec![vec![
+            Vec4::new(-1.0, -1.0, 0.0, 1.0),
+            Vec4::new(-1.0, 1.0, 0.0, 1.0),
+            Vec4::new(1.0, 1.0, 0.0, 1.0),
+        ]
+        .into()],
+        ..Context::new(Program::default(), Frame::new(4, 4))
+    };
+    context.draw_elements(Topology::Triangles, 0, &Indices::U16(&[0, 1, 3]));
+}
+
+#[test]
+#[should_panic(expected = "attribute color has 2 vertices, the position buffer has 3")]
+fn test_draw_short_attribute_buffer() {
+    use crate::engine::program::{Attribute, Interpolation};
+
+    let mut context = Context {
+        current_buffers: vec![
+            VertexBuffer::from_f32(&[-1.0, -1.0, -1.0, 1.0, 1.0, 1.0], 2),
+            VertexBuffer::from_f32(&[1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0], 4),
+        ],
+        ..Context::new(
+            Program {
+                attributes: vec![Attribute {
+                    index: 1,
+                    name: "color".to_string(),
+                    interpolation: Interpolation::Smooth,
+                    layout: None,
+                }],
+                ..Program::default()
+            },
+            Frame::new(4, 4),
+        )
+    };
+    context.draw_triangles(0);
+}
+
+#[test]
+fn test_typed_shader() {
+    use crate::engine::shader::Flat;
+
+    struct Checker;
+    impl Shader for Checker {
+        type Vertex = (Vec2, Vec3);
+        type Varying = (Vec2, Flat<Vec3>);
+        type Uniforms = f64;
+
+        fn vertex(&self, vertex: &Self::Vertex, depth: &f64) -> (Vec4, Self::Varying) {
+            let (position, color) = vertex;
+            (
+                Vec4::new(position.x(), position.y(), *depth, 1.0),
+                (*position, Flat(*color)),
+            )
+        }
+        fn fragment(&self, varying: &Self::Varying, _: &f64, _: Vec2) -> Option<Vec4> {
+            let (position, Flat(color)) = varying;
+            // a hole in the left half of the screen
+            if position.x() < 0.0 {
+                return None;
+            }
+            Some(Vec4::new(color.x(), color.y(), color.z(), 1.0))
+        }
+    }
+
+    let mut context = Context::new(Program::default(), Frame::new(8, 8));
+    context.clear();
+    let red = Vec3::new(1.0, 0.0, 0.0);
+    let quad = [
+        (Vec2::new(-1.0, -1.0), Vec3::BLACK),
+        (Vec2::new(-1.0, 1.0), Vec3::BLACK),
+        (Vec2::new(1.0, 1.0), red),
+        (Vec2::new(1.0, -1.0), red),
+    ];
+    context.draw(&Checker, &0.0, &quad, Topology::TriangleFan, None);
+
+    let frame = &context.current_frame;
+    let pixel = |coord| {
+        (
+            frame.get_color(&coord).unwrap(),
+            frame.get_depth(&coord).unwrap(),
+        )
+    };
+    assert_eq!(
+        pixel((1, 4)),
+        ((0, 0, 0, 0), 1.0),
+        "a discarded fragment was written"
+    );
+    assert_eq!(pixel((6, 4)), ((255, 0, 0, 255), 0.5));
+}
+
+#[test]
+fn test_binned_raster() {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    struct Colored;
+    impl Shader for Colored {
+        type Vertex = (Vec4, Vec4);
+        type Varying = Vec4;
+        type Uniforms = ();
+
+        fn vertex(&self, vertex: &Self::Vertex, _: &()) -> (Vec4, Vec4) {
+            *vertex
+        }
+        fn fragment(&self, color: &Vec4, _: &(), _: Vec2) -> Option<Vec4> {
+            Some(*color)
+        }
+    }
+
+    // overlapping translucent triangles, the result depends on the order they are blended in
+    let mut rng = StdRng::seed_from_u64(7);
+    let vertices: Vec<(Vec4, Vec4)> = (0..60 * 3)
+        .map(|_| {
+            (
+                Vec4::new(rng.gen_range(-1.2, 1.2), rng.gen_range(-1.2, 1.2), 0.0, 1.0),
+                Vec4::new(rng.gen(), rng.gen(), rng.gen(), 0.5),
+            )
+        })
+        .collect();
+    let draw = |raster: RasterState| {
+        let mut context = Context {
+            depth: DepthState {
+                func: CompareFunc::Always,
+                ..DepthState::default()
+            },
+            blend: BlendState::ALPHA,
+            raster,
+            ..Context::new(Program::default(), Frame::new(37, 29))
+        };
+        context.clear();
+        // both windings, half of the triangles are culled
+        context.draw(&Colored, &(), &vertices, Topology::Triangles, None);
+        context.current_frame
+    };
+
+    let serial = draw(RasterState {
+        bin_size: 64,
+        threads: 1,
+        ..RasterState::default()
+    });
+    assert!(serial.color.iter().any(|color| *color != (0, 0, 0, 0)));
+    for (bin_size, threads) in [(8, 3), (5, 8), (1, 2)].iter() {
+        let binned = draw(RasterState {
+            bin_size: *bin_size,
+            threads: *threads,
+            ..RasterState::default()
+        });
+        for i in 0..serial.color.len() {
+            assert_eq!(
+                (serial.color[i], serial.depth[i]),
+                (binned.color[i], binned.depth[i]),
+                "pixel {} differs with {} pixel bins on {} threads",
+                i,
+                bin_size,
+                threads
+            );
+        }
+    }
+}
+
+#[test]
+fn test_visibility_buffer() {
+    use std::sync::Arc;
+
+    let invocations = Arc::new(AtomicUsize::new(0));
+    let counter = invocations.clone();
+    let quad = |z: f64| {
+        vec![
+            Vec4::new(-1.0, -1.0, z, 1.0),
+            Vec4::new(-1.0, 1.0, z, 1.0),
+            Vec4::new(1.0, 1.0, z, 1.0),
+            Vec4::new(-1.0, -1.0, z, 1.0),
+            Vec4::new(1.0, 1.0, z, 1.0),
+            Vec4::new(1.0, -1.0, z, 1.0),
+        ]
+    };
+    let mut context = Context {
+        // the far quad first, every pixel passes the depth test twice
+        current_buffers: vec![[quad(0.5), quad(-0.5)].concat().into()],
+        raster: RasterState {
+            bin_size: 8,
+            threads: 2,
+            ..RasterState::default()
+        },
+        ..Context::new(
+            Program {
+                vertex_shader: Box::new(|_, _, position| position),
+                fragment_shader: Box::new(move |_, _, _| {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    Vec4::new(1.0, 1.0, 1.0, 1.0)
+                }),
+                outputs: vec![],
+                attributes: vec![],
+                uniforms: vec![],
+            },
+            Frame::new(16, 12),
+        )
+    };
+    context.clear();
+    context.draw_triangles(0);
+
+    assert_eq!(invocations.load(Ordering::Relaxed), 16 * 12);
+    let frame = &context.current_frame;
+    assert!(frame
+        .color
+        .iter()
+        .all(|color| *color == (255, 255, 255, 255)));
+    assert!(frame.depth.iter().all(|z| *z == 0.25));
+    // the near quad is the third and fourth triangle
+    assert!(frame
+        .visibility
+        .iter()
+        .all(|v| v.triangle == 2 || v.triangle == 3));
+    let [alpha, beta, gamma] = frame.visibility[0].weights();
+    assert!((alpha + beta + gamma - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_line_modes() {
+    let mut context = Context {
+        // a quad over the whole screen, further away on the right
+        current_buffers: vec![vec![
+            Vec4::new(-1.0, -1.0, -0.5, 1.0),
+            Vec4::new(-1.0, 1.0, -0.5, 1.0),
+            Vec4::new(1.0, 1.0, 0.5, 1.0),
+            Vec4::new(1.0, -1.0, 0.5, 1.0),
+        ]
+        .into()],
+        raster: RasterState {
+            line_width: 3.0,
+            point_size: 4.0,
+            ..RasterState::default()
+        },
+        ..Context::new(Program::default(), Frame::new(16, 16))
+    };
+    let drawn = |context: &Context| {
+        let frame = &context.current_frame;
+        (0..16 * 16)
+            .filter(|i| frame.color[*i].3 > 0)
+            .map(|i| (i % 16, i / 16))
+            .collect::<Vec<_>>()
+    };
+
+    // the diagonal from the bottom left to the top right corner is three pixels high
+    context.clear();
+    context.draw_elements(Topology::Lines, 0, &Indices::U16(&[0, 2]));
+    let line = drawn(&context);
+    assert_eq!(line.len(), 16 * 3 - 3, "{:?}", line);
+    assert!((7..=9).all(|y| line.contains(&(8, y))), "{:?}", line);
+    assert!(!line.contains(&(8, 6)) && !line.contains(&(8, 10)));
+
+    context.clear();
+    context.draw_elements(Topology::Points, 0, &Indices::U16(&[2]));
+    assert_eq!(drawn(&context), vec![(14, 0), (15, 0), (14, 1), (15, 1)]);
+
+    context.raster.line_width = 1.0;
+    context.raster.polygon_mode = PolygonMode::Line;
+    context.clear();
+    context.draw_arrays(Topology::TriangleFan, 0);
+    let edges = drawn(&context);
+    assert!(edges.contains(&(8, 8)) && edges.contains(&(0, 5)) && edges.contains(&(5, 0)));
+    assert!(
+        !edges.contains(&(4, 8)),
+        "the inside of the quad was filled"
+    );
+
+    context.raster.polygon_mode = PolygonMode::Wireframe([1.0, 0.0, 0.0, 1.0]);
+    context.clear();
+    context.draw_arrays(Topology::TriangleFan, 0);
+    let frame = &context.current_frame;
+    assert_eq!(frame.get_color(&(4, 8)), Some((0, 0, 0, 255)));
+    // the diagonal of the quad beats the triangles on both sides of it in the depth test
+    for x in 1..16 {
+        assert_eq!(
+            frame.get_color(&(x, 16 - x)),
+            Some((255, 0, 0, 255)),
+            "{}",
+            x
+        );
+    }
+    let fill = 0.5 + 0.5 * (8.5 / 16.0 - 0.5);
+    assert!((f64::from(frame.get_depth(&(8, 8)).unwrap()) - fill).abs() < 1e-3);
+}
+
+#[test]
+fn test_multisample() {
+    let quad = |left: f64, right: f64| {
+        vec![
+            Vec4::new(left, -1.0, 0.0, 1.0),
+            Vec4::new(left, 1.0, 0.0, 1.0),
+            Vec4::new(right, 1.0, 0.0, 1.0),
+            Vec4::new(left, -1.0, 0.0, 1.0),
+            Vec4::new(right, 1.0, 0.0, 1.0),
+            Vec4::new(right, -1.0, 0.0, 1.0),
+        ]
+    };
+    let context = |alpha: f64, blend: BlendState| Context {
+        // the right edge of the first quad is at x = 4.5 in pixels
+        current_buffers: vec![quad(-1.0, 0.125).into(), quad(-1.0, 1.0).into()],
+        blend,
+        ..Context::new(
+            Program {
+                vertex_shader: Box::new(|_, _, position| position),
+                fragment_shader: Box::new(move |_, _, _| Vec4::new(1.0, 1.0, 1.0, alpha)),
+                outputs: vec![],
+                attributes: vec![],
+                uniforms: vec![],
+            },
+            Frame::multisample(8, 4, 4),
+        )
+    };
+
+    let mut edge = context(1.0, BlendState::default());
+    edge.clear();
+    edge.draw_triangles(0);
+    let frame = &edge.current_frame;
+    assert_eq!(frame.color.len(), 8 * 4 * 4);
+    // the edge covers the first and third sample of column 4
+    assert_eq!(frame.get_color(&(3, 1)), Some((255, 255, 255, 255)));
+    assert_eq!(frame.get_color(&(4, 1)), Some((128, 128, 128, 128)));
+    assert_eq!(frame.get_color(&(5, 1)), Some((0, 0, 0, 0)));
+    let index = frame.index(&(4, 1)).unwrap() * 4;
+    assert_eq!(
+        frame.depth[index..index + 4]
+            .iter()
+            .map(|z| *z < 1.0)
+            .collect::<Vec<_>>(),
+        vec![true, false, true, false]
+    );
+    let resolved = frame.resolve();
+    assert_eq!(resolved.samples, 1);
+    assert_eq!(resolved.get_color(&(4, 1)), frame.get_color(&(4, 1)));
+
+    let mut coverage = context(
+        0.5,
+        BlendState {
+            alpha_to_coverage: true,
+            ..BlendState::default()
+        },
+    );
+    coverage.clear();
+    coverage.draw_triangles(1);
+    // half of the samples of every pixel, whatever the order of the triangles
+    assert!(
+        (0..32).all(|i| coverage.current_frame.color[i * 4..i * 4 + 4]
+            == [
+                (255, 255, 255, 128),
+                (255, 255, 255, 128),
+                (0, 0, 0, 0),
+                (0, 0, 0, 0)
+            ])
+    );
+}
+
+#[test]
+fn test_stencil() {
+    let quad = |left: f64, right: f64, z: f64| {
+        vec![
+            Vec4::new(left, -1.0, z, 1.0),
+            Vec4::new(left, 1.0, z, 1.0),
+            Vec4::new(right, 1.0, z, 1.0),
+            Vec4::new(left, -1.0, z, 1.0),
+            Vec4::new(right, 1.0, z, 1.0),
+            Vec4::new(right, -1.0, z, 1.0),
+        ]
+    };
+    let program = |color: Vec4| Program {
+        vertex_shader: Box::new(|_, _, position| position),
+        fragment_shader: Box::new(move |_, _, _| color),
+        outputs: vec![],
+        attributes: vec![],
+        uniforms: vec![],
+    };
+    let mut back = quad(-1.0, 1.0, 0.5);
+    back.reverse();
+    let mut context = Context {
+        current_buffers: vec![
+            quad(-0.5, 0.5, 0.0).into(),
+            quad(-1.0, 1.0, 0.5).into(),
+            back.into(),
+            quad(-1.0, 0.0, -0.5).into(),
+        ],
+        ..Context::new(program(Vec4::new(1.0, 1.0, 1.0, 1.0)), Frame::new(8, 4))
+    };
+    let stencil_row = |context: &Context| context.current_frame.stencil[8..16].to_vec();
+
+    // an outline: mark the object, then draw a larger shape where it is not marked
+    context.clear();
+    context.stencil = StencilState::both(StencilFace {
+        reference: 1,
+        pass: StencilOp::Replace,
+        ..StencilFace::default()
+    });
+    context.draw_triangles(0);
+    assert_eq!(stencil_row(&context), vec![0, 0, 1, 1, 1, 1, 0, 0]);
+    context.clear_depth();
+    context.current_program = program(Vec4::new(1.0, 0.0, 0.0, 1.0));
+    context.stencil = StencilState::both(StencilFace {
+        func: CompareFunc::NotEqual,
+        reference: 1,
+        ..StencilFace::default()
+    });
+    context.draw_triangles(1);
+    let frame = &context.current_frame;
+    assert_eq!(frame.get_color(&(1, 1)), Some((255, 0, 0, 255)));
+    assert_eq!(frame.get_color(&(2, 1)), Some((255, 255, 255, 255)));
+    assert_eq!(frame.get_color(&(6, 1)), Some((255, 0, 0, 255)));
+
+    // z-fail shadow volume: back faces behind the occluder count up, front faces count down
+    context.stencil = StencilState::default();
+    context.clear();
+    context.draw_triangles(3);
+    context.depth.write = false;
+    context.blend.write_mask = [false; 4];
+    context.raster.cull_back_faces = false;
+    context.stencil = StencilState {
+        enabled: true,
+        front: StencilFace {
+            depth_fail: StencilOp::DecrWrap,
+            ..StencilFace::default()
+        },
+        back: StencilFace {
+            depth_fail: StencilOp::IncrWrap,
+            ..StencilFace::default()
+        },
+        clear_value: 0,
+    };
+    context.draw_triangles(2);
+    assert_eq!(stencil_row(&context), vec![1, 1, 1, 1, 0, 0, 0, 0]);
+    context.draw_triangles(1);
+    assert_eq!(stencil_row(&context), vec![0; 8]);
+    assert_eq!(
+        context.current_frame.get_color(&(0, 1)),
+        Some((255, 0, 0, 255))
+    );
+}
+
+#[test]
+fn test_render_to_texture() {
+    use crate::engine::program::Uniform;
+    use crate::engine::texture::Filter;
+    use std::sync::Arc;
+
+    // the left half at depth 0.25, red with a normal in the first attachment
+    let left = vec![
+        Vec4::new(-1.0, -1.0, -0.5, 1.0),
+        Vec4::new(-1.0, 1.0, -0.5, 1.0),
+        Vec4::new(0.0, 1.0, -0.5, 1.0),
+        Vec4::new(-1.0, -1.0, -0.5, 1.0),
+        Vec4::new(0.0, 1.0, -0.5, 1.0),
+        Vec4::new(0.0, -1.0, -0.5, 1.0),
+    ];
+    let full: Vec<Vec4> = left
+        .iter()
+        .map(|v| Vec4::new(v.x() * 2.0 + 1.0, v.y(), 0.0, 1.0))
+        .collect();
+    let mut context = Context {
+        current_buffers: vec![left.into(), full.into()],
+        ..Context::new(
+            Program {
+                fragment_shader: Box::new(|_, _, _| Vec4::new(1.0, 0.0, 0.0, 1.0)),
+                outputs: vec![Box::new(|_, _, _| Vec4::new(0.0, 0.0, -1.0, 1.0))],
+                ..Program::default()
+            },
+            Frame::new(4, 2),
+        )
+    };
+    let mut offscreen = Frame::new(4, 2);
+    offscreen.add_attachment();
+    let screen = context.bind_frame(offscreen);
+    context.clear();
+    context.draw_triangles(0);
+    let offscreen = context.bind_frame(screen);
+    assert_eq!(offscreen.attachments[0][0], [0.0, 0.0, -1.0, 1.0]);
+    assert_eq!(offscreen.attachments[0][3], [0.0; 4]);
+
+    let textures = vec![
+        offscreen.color_texture(),
+        offscreen.attachment_texture(0),
+        offscreen.depth_texture(),
+    ];
+    // a second pass reads the first one back pixel by pixel
+    for (texture, expected) in textures.into_iter().zip(vec![
+        [(255, 0, 0, 255), (0, 0, 0, 0)],
+        [(0, 0, 0, 255), (0, 0, 0, 0)],
+        [(64, 64, 64, 255), (255, 255, 255, 255)],
+    ]) {
+        let mut texture = texture;
+        texture.filter = Filter::Nearest;
+        context.current_program = Program {
+            fragment_shader: Box::new(|_, uniforms, coord| match &uniforms[0] {
+                ShaderData::Sampler(texture) => {
+                    texture.sample((coord.x() + 0.5) / 4.0, 1.0 - (coord.y() + 0.5) / 2.0)
+                }
+                _ => panic!("the uniform is not a texture"),
+            }),
+            uniforms: vec![Uniform {
+                name: "previous_pass".to_string(),
+                value: ShaderData::Sampler(Arc::new(texture)),
+            }],
+            ..Program::default()
+        };
+        context.clear();
+        context.draw_triangles(1);
+        let frame = &context.current_frame;
+        assert_eq!(frame.get_color(&(1, 1)), Some(expected[0]));
+        assert_eq!(frame.get_color(&(2, 1)), Some(expected[1]));
+    }
+}
+
+#[test]
+fn test_viewport_scissor() {
+    let quad = |bottom: f64, z: f64| {
+        vec![
+            Vec4::new(-1.0, bottom, z, 1.0),
+            Vec4::new(-1.0, 1.0, z, 1.0),
+            Vec4::new(1.0, 1.0, z, 1.0),
+            Vec4::new(-1.0, bottom, z, 1.0),
+            Vec4::new(1.0, 1.0, z, 1.0),
+            Vec4::new(1.0, bottom, z, 1.0),
+        ]
+    };
+    let program = |r: f64, g: f64, b: f64| Program {
+        fragment_shader: Box::new(move |_, _, _| Vec4::new(r, g, b, 1.0)),
+        ..Program::default()
+    };
+    let mut context = Context {
+        current_buffers: vec![quad(-1.0, 0.0).into(), quad(0.0, -0.5).into()],
+        ..Context::new(program(1.0, 0.0, 0.0), Frame::new(8, 4))
+    };
+    let (red, green, blue) = ((255, 0, 0, 255), (0, 255, 0, 255), (0, 0, 255, 255));
+    context.clear();
+
+    // split screen, the right view is drawn into the back half of the depth range
+    let full = Viewport::full(8, 4);
+    context.raster.viewport = Some(Viewport { width: 4.0, ..full });
+    context.draw_triangles(0);
+    let frame = &context.current_frame;
+    assert_eq!(frame.get_color(&(3, 2)), Some(red));
+    assert_eq!(frame.get_color(&(4, 2)), Some((0, 0, 0, 0)));
+    context.raster.viewport = Some(Viewport {
+        x: 4.0,
+        width: 4.0,
+        min_depth: 0.5,
+        ..full
+    });
+    context.current_program = program(0.0, 1.0, 0.0);
+    context.draw_triangles(0);
+    let frame = &context.current_frame;
+    assert_eq!(frame.get_color(&(3, 2)), Some(red));
+    assert_eq!(frame.get_color(&(4, 2)), Some(green));
+    assert_eq!(frame.get_depth(&(0, 0)), Some(0.5));
+    assert_eq!(frame.get_depth(&(7, 0)), Some(0.75));
+
+    // the upper half of ndc is the first rows of the frame
+    context.raster.viewport = None;
+    context.current_program = program(0.0, 0.0, 1.0);
+    context.draw_triangles(1);
+    let frame = &context.current_frame;
+    assert!((0..8).all(|x| frame.get_color(&(x, 1)) == Some(blue)));
+    assert_eq!(frame.get_color(&(0, 2)), Some(red));
+    assert_eq!(frame.get_color(&(7, 2)), Some(green));
+
+    // clears and draws stay inside the scissor rectangle
+    context.raster.scissor = Some(Scissor {
+        x: 3,
+        y: 1,
+        width: 2,
+        height: 2,
+    });
+    context.clear();
+    let frame = &context.current_frame;
+    assert_eq!(frame.get_color(&(3, 1)), Some((0, 0, 0, 0)));
+    assert_eq!(frame.get_depth(&(4, 2)), Some(1.0));
+    assert_eq!(frame.get_color(&(2, 1)), Some(blue));
+    context.current_program = program(1.0, 1.0, 1.0);
+    context.draw_triangles(0);
+    let frame = &context.current_frame;
+    assert_eq!(frame.get_color(&(4, 2)), Some((255, 255, 255, 255)));
+    assert_eq!(frame.get_color(&(2, 2)), Some(red));
+    assert_eq!(frame.get_color(&(5, 1)), Some(blue));
 }
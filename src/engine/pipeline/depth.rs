@@ -0,0 +1,169 @@
+/**
+ * how an incoming value is compared against the one in the buffer, the test passes when
+ * `incoming <op> stored` holds
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareFunc {
+    Never,
+    Less,
+    Equal,
+    LEqual,
+    Greater,
+    NotEqual,
+    GEqual,
+    Always,
+}
+
+impl CompareFunc {
+    pub fn test<T: PartialOrd>(self, incoming: T, stored: T) -> bool {
+        match self {
+            CompareFunc::Never => false,
+            CompareFunc::Less => incoming < stored,
+            CompareFunc::Equal => incoming == stored,
+            CompareFunc::LEqual => incoming <= stored,
+            CompareFunc::Greater => incoming > stored,
+            CompareFunc::NotEqual => incoming != stored,
+            CompareFunc::GEqual => incoming >= stored,
+            CompareFunc::Always => true,
+        }
+    }
+}
+
+/**
+ * depth = factor * the steepest depth slope of the triangle + units * the smallest step of the buffer,
+ * added before the depth test
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PolygonOffset {
+    pub factor: f64,
+    pub units: f64,
+}
+
+/**
+ * the depth buffer settings of a `Context`. depth is stored in [0, 1], near is 0 unless `reversed`
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthState {
+    pub func: CompareFunc,
+    /**
+     * write mask, passing fragments only update the buffer when it is set
+     */
+    pub write: bool,
+    pub clear_value: f32,
+    /**
+     * stores 1 - depth, far away depth lands near 0 where f32 is most precise.
+     * pair it with `Greater` and a clear value of 0
+     */
+    pub reversed: bool,
+    pub polygon_offset: Option<PolygonOffset>,
+}
+
+impl Default for DepthState {
+    fn default() -> DepthState {
+        DepthState {
+            func: CompareFunc::Less,
+            write: true,
+            clear_value: 1.0,
+            reversed: false,
+            polygon_offset: None,
+        }
+    }
+}
+
+impl DepthState {
+    pub const REVERSED: DepthState = DepthState {
+        func: CompareFunc::Greater,
+        write: true,
+        clear_value: 0.0,
+        reversed: true,
+        polygon_offset: None,
+    };
+
+    /**
+     * the value stored in the buffer for a ndc z in [-1, 1]
+     */
+    pub fn window_depth(&self, ndc_z: f64) -> f64 {
+        let depth = ndc_z * 0.5 + 0.5;
+        if self.reversed {
+            1.0 - depth
+        } else {
+            depth
+        }
+    }
+
+    /**
+     * the polygon offset of a triangle with window depths `depths` at pixel positions `coords`
+     */
+    pub fn offset(&self, coords: [(f64, f64); 3], depths: [f64; 3]) -> f64 {
+        let PolygonOffset { factor, units } = match self.polygon_offset {
+            Some(offset) => offset,
+            None => return 0.0,
+        };
+        let (e1, e2) = (
+            (
+                coords[1].0 - coords[0].0,
+                coords[1].1 - coords[0].1,
+                depths[1] - depths[0],
+            ),
+            (
+                coords[2].0 - coords[0].0,
+                coords[2].1 - coords[0].1,
+                depths[2] - depths[0],
+            ),
+        );
+        // the plane normal of the triangle gives dz/dx = -nx / nz and dz/dy = -ny / nz
+        let nx = e1.1 * e2.2 - e1.2 * e2.1;
+        let ny = e1.2 * e2.0 - e1.0 * e2.2;
+        let nz = e1.0 * e2.1 - e1.1 * e2.0;
+        let slope = if nz == 0.0 {
+            0.0
+        } else {
+            (nx / nz).abs().max((ny / nz).abs())
+        };
+        // for a float buffer the smallest step depends on the exponent of the largest depth
+        let max_depth = depths.iter().fold(0.0f64, |max, d| max.max(d.abs()));
+        let step = if max_depth > 0.0 {
+            2f64.powi(max_depth.log2().floor() as i32 - f32::MANTISSA_DIGITS as i32 + 1)
+        } else {
+            f64::from(f32::MIN_POSITIVE)
+        };
+        factor * slope + units * step
+    }
+}
+
+#[test]
+fn test_depth_state() {
+    assert!(CompareFunc::Less.test(0.2, 0.5));
+    assert!(!CompareFunc::Less.test(0.5, 0.5));
+    assert!(CompareFunc::LEqual.test(0.5, 0.5));
+    assert!(CompareFunc::GEqual.test(0.5, 0.5) && !CompareFunc::Greater.test(0.5, 0.5));
+    assert!(!CompareFunc::Never.test(0.0, 1.0) && CompareFunc::Always.test(1.0, 0.0));
+
+    let depth = DepthState::default();
+    assert_eq!(
+        (depth.window_depth(-1.0), depth.window_depth(1.0)),
+        (0.0, 1.0)
+    );
+    assert_eq!(DepthState::REVERSED.window_depth(-1.0), 1.0);
+
+    let offset = DepthState {
+        polygon_offset: Some(PolygonOffset {
+            factor: 1.0,
+            units: 0.0,
+        }),
+        ..depth
+    };
+    // depth grows by 0.01 per pixel along x
+    let coords = [(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)];
+    let slope = offset.offset(coords, [0.5, 0.6, 0.5]);
+    assert!((slope - 0.01).abs() < 1e-12);
+    let units = DepthState {
+        polygon_offset: Some(PolygonOffset {
+            factor: 0.0,
+            units: 1.0,
+        }),
+        ..depth
+    };
+    let step = units.offset(coords, [0.5, 0.6, 0.5]);
+    assert_eq!(step as f32, 0.5f32.next_up() - 0.5);
+}
@@ -1,4 +1,15 @@
+mod bin;
+mod blend;
 mod context;
+mod depth;
+mod primitive;
 mod raster;
+mod stencil;
+mod viewport;
 
-pub use context::*;
\ No newline at end of file
+pub use blend::*;
+pub use context::*;
+pub use depth::*;
+pub use primitive::*;
+pub use stencil::*;
+pub use viewport::*;
@@ -0,0 +1,167 @@
+use super::viewport::{Scissor, Viewport};
+
+/**
+ * how a list of vertices is grouped into primitives, the same as the gl draw modes
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Topology {
+    Points,
+    Lines,
+    LineStrip,
+    Triangles,
+    TriangleStrip,
+    TriangleFan,
+}
+
+impl Topology {
+    /**
+     * number of vertices in one primitive after assembly
+     */
+    pub fn primitive_size(self) -> usize {
+        match self {
+            Topology::Points => 1,
+            Topology::Lines | Topology::LineStrip => 2,
+            Topology::Triangles | Topology::TriangleStrip | Topology::TriangleFan => 3,
+        }
+    }
+    /**
+     * positions into a vertex list of `count` vertices, `primitive_size` of them per primitive.
+     * the last vertex of every primitive is the provoking vertex, strips keep the winding of the first triangle
+     */
+    pub fn assemble(self, count: usize) -> Vec<usize> {
+        let mut res = vec![];
+        match self {
+            Topology::Points => res.extend(0..count),
+            Topology::Lines => res.extend(0..count / 2 * 2),
+            Topology::Triangles => res.extend(0..count / 3 * 3),
+            Topology::LineStrip => {
+                for i in 1..count {
+                    res.extend_from_slice(&[i - 1, i]);
+                }
+            }
+            Topology::TriangleStrip => {
+                for i in 2..count {
+                    if i % 2 == 0 {
+                        res.extend_from_slice(&[i - 2, i - 1, i]);
+                    } else {
+                        res.extend_from_slice(&[i - 1, i - 2, i]);
+                    }
+                }
+            }
+            Topology::TriangleFan => {
+                for i in 2..count {
+                    res.extend_from_slice(&[0, i - 1, i]);
+                }
+            }
+        }
+        res
+    }
+}
+
+/**
+ * an index buffer, 16 bit indices take half the memory when there are few vertices
+ */
+#[derive(Debug, Clone, Copy)]
+pub enum Indices<'a> {
+    U16(&'a [u16]),
+    U32(&'a [u32]),
+}
+
+impl Indices<'_> {
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::U16(indices) => indices.len(),
+            Indices::U32(indices) => indices.len(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn get(&self, i: usize) -> Option<usize> {
+        match self {
+            Indices::U16(indices) => indices.get(i).map(|index| *index as usize),
+            Indices::U32(indices) => indices.get(i).map(|index| *index as usize),
+        }
+    }
+    pub fn to_vec(self) -> Vec<usize> {
+        (0..self.len()).filter_map(|i| self.get(i)).collect()
+    }
+}
+
+/**
+ * what is drawn of a triangle, like glPolygonMode
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolygonMode {
+    Fill,
+    /**
+     * only the edges, as lines shaded like the triangle
+     */
+    Line,
+    /**
+     * filled, then the edges are drawn over the fill in a solid rgba color
+     */
+    Wireframe([f64; 4]),
+}
+
+/**
+ * how primitives are turned into fragments. the frame is cut into bins of `bin_size` pixels,
+ * the triangles in every bin are rasterized and shaded by one of `threads` workers
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RasterState {
+    pub bin_size: usize,
+    pub threads: usize,
+    /**
+     * in pixels across the line, rounded to a whole number
+     */
+    pub line_width: f64,
+    /**
+     * the side of the square drawn for a point, in pixels
+     */
+    pub point_size: f64,
+    pub polygon_mode: PolygonMode,
+    /**
+     * drops the triangles that are counter-clockwise in ndc, stencil shadow volumes need both sides
+     */
+    pub cull_back_faces: bool,
+    /**
+     * where ndc lands in the frame, None is the whole frame
+     */
+    pub viewport: Option<Viewport>,
+    pub scissor: Option<Scissor>,
+}
+
+impl Default for RasterState {
+    fn default() -> RasterState {
+        RasterState {
+            bin_size: 64,
+            threads: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            line_width: 1.0,
+            point_size: 1.0,
+            polygon_mode: PolygonMode::Fill,
+            cull_back_faces: true,
+            viewport: None,
+            scissor: None,
+        }
+    }
+}
+
+#[test]
+fn test_assemble() {
+    assert_eq!(Topology::Triangles.assemble(7), vec![0, 1, 2, 3, 4, 5]);
+    assert_eq!(Topology::Lines.assemble(3), vec![0, 1]);
+    assert_eq!(Topology::LineStrip.assemble(3), vec![0, 1, 1, 2]);
+    assert_eq!(
+        Topology::TriangleStrip.assemble(5),
+        vec![0, 1, 2, 2, 1, 3, 2, 3, 4]
+    );
+    assert_eq!(Topology::TriangleFan.assemble(4), vec![0, 1, 2, 0, 2, 3]);
+    assert_eq!(Topology::TriangleFan.assemble(2), Vec::<usize>::new());
+
+    let indices = [3u16, 1, 2];
+    assert_eq!(Indices::U16(&indices).to_vec(), vec![3, 1, 2]);
+    assert_eq!(Indices::U32(&[7, 8]).get(1), Some(8));
+}
@@ -1,7 +1,10 @@
 use crate::engine::base::Vec4;
 
 pub fn raster_line(p0: &Vec4, p1: &Vec4) -> Vec<((usize, usize), f64)> {
-    let (alpha, beta) = compute_line(p0, p1);
+    // dy * x - dx * y + c, unlike y = alpha * x + beta it also holds for vertical lines
+    let (dx, dy) = (p1.x() - p0.x(), p1.y() - p0.y());
+    let c = p1.x() * p0.y() - p0.x() * p1.y();
+    let line = |point: (f64, f64)| dy * point.0 - dx * point.1 + c;
 
     let mut points = vec![];
 
@@ -32,7 +35,7 @@ pub fn raster_line(p0: &Vec4, p1: &Vec4) -> Vec<((usize, usize), f64)> {
         },
     );
 
-    let should_switch_x_and_y = alpha.abs() > 1.0;
+    let should_switch_x_and_y = dy.abs() > dx.abs();
     let mut mid_point = (0.0, 0.0);
 
     'l: loop {
@@ -52,9 +55,7 @@ pub fn raster_line(p0: &Vec4, p1: &Vec4) -> Vec<((usize, usize), f64)> {
             mid_point.1 = cur_point.1 + y_delta / 2.0;
         }
         // 如果中点与下面的点在同一边, 则应该选择上面的点
-        if (alpha * mid_point.0 + beta - mid_point.1) * (alpha * cur_point.0 + beta - cur_point.1)
-            > 0.0
-        {
+        if line(mid_point) * line(cur_point) > 0.0 {
             if should_switch_x_and_y {
                 cur_point.0 += x_delta;
             } else {
@@ -64,7 +65,11 @@ pub fn raster_line(p0: &Vec4, p1: &Vec4) -> Vec<((usize, usize), f64)> {
         points.push((cur_point.0.round() as usize, cur_point.1.round() as usize));
     }
 
-    let unit_interpolate = 1.0 / (points.len() - 1) as f64;
+    let unit_interpolate = if points.len() > 1 {
+        1.0 / (points.len() - 1) as f64
+    } else {
+        0.0
+    };
 
     points
         .into_iter()
@@ -73,6 +78,32 @@ pub fn raster_line(p0: &Vec4, p1: &Vec4) -> Vec<((usize, usize), f64)> {
         .collect()
 }
 
+/**
+ * `raster_line` `width` pixels wide. every pixel is repeated across the minor axis of the line,
+ * the way gl draws wide lines that are not antialiased
+ */
+pub fn raster_wide_line(p0: &Vec4, p1: &Vec4, width: f64) -> Vec<((usize, usize), f64)> {
+    let width = width.round().max(1.0) as isize;
+    let x_major = (p1.x() - p0.x()).abs() >= (p1.y() - p0.y()).abs();
+    let mut points = vec![];
+    for ((x, y), t) in raster_line(p0, p1) {
+        for k in 0..width {
+            let offset = k - (width - 1) / 2;
+            let minor = if x_major { y } else { x } as isize + offset;
+            if minor < 0 {
+                continue;
+            }
+            let coord = if x_major {
+                (x, minor as usize)
+            } else {
+                (minor as usize, y)
+            };
+            points.push((coord, t));
+        }
+    }
+    points
+}
+
 #[test]
 fn test_raster_line() {
     const EPSILON: f64 = 1e-9;
@@ -163,6 +194,24 @@ fn test_raster_line() {
     }
 }
 
+#[test]
+fn test_raster_wide_line() {
+    let p0 = Vec4::new(2.0, 3.0, 0.0, 1.0);
+    let p1 = Vec4::new(12.0, 10.0, 0.0, 1.0);
+    assert_eq!(raster_wide_line(&p0, &p1, 1.0), raster_line(&p0, &p1));
+
+    let points = raster_wide_line(&p0, &p1, 3.0);
+    assert_eq!(points.len(), 33);
+    // the line is x major, the pixels are stacked vertically around every pixel of the thin line
+    assert_eq!(
+        points[..3].iter().map(|p| p.0).collect::<Vec<_>>(),
+        vec![(2, 2), (2, 3), (2, 4)]
+    );
+    let vertical = raster_wide_line(&p0, &Vec4::new(0.5, 10.0, 0.0, 1.0), 2.0);
+    assert!(vertical.iter().any(|p| p.0 == (0, 10)) && vertical.iter().any(|p| p.0 == (1, 10)));
+}
+
+#[cfg(test)]
 fn compute_line(p0: &Vec4, p1: &Vec4) -> (f64, f64) {
     // alpha * p0.x() + beta === p0.y()
     // alpha * p1.x() + beta === p1.y()
@@ -1,5 +1,7 @@
 mod line;
+mod point;
 mod triangle;
 
 pub use line::*;
+pub use point::*;
 pub use triangle::*;
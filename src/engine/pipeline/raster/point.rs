@@ -0,0 +1,32 @@
+use crate::engine::base::Vec2;
+
+/**
+ * the pixels whose centers are covered by a square point of `size` pixels around `center`
+ */
+pub fn raster_point(center: &Vec2, size: f64) -> Vec<(usize, usize)> {
+    let half = size.max(1.0) / 2.0;
+    // pixel i covers the center i + 0.5, a center on the lower edge of the square is left out
+    let range = |c: f64| {
+        let first = ((c - half - 0.5).floor() + 1.0).max(0.0) as usize;
+        let last = ((c + half - 0.5).floor() + 1.0).max(0.0) as usize;
+        first..last
+    };
+    let mut points = vec![];
+    for y in range(center.y()) {
+        for x in range(center.x()) {
+            points.push((x, y));
+        }
+    }
+    points
+}
+
+#[test]
+fn test_raster_point() {
+    assert_eq!(raster_point(&Vec2::new(4.0, 0.0), 1.0), vec![(4, 0)]);
+    assert_eq!(raster_point(&Vec2::new(3.7, 2.2), 1.0), vec![(3, 2)]);
+    assert_eq!(
+        raster_point(&Vec2::new(3.0, 3.0), 2.0),
+        vec![(2, 2), (3, 2), (2, 3), (3, 3)]
+    );
+    assert_eq!(raster_point(&Vec2::new(0.5, 0.5), 4.0).len(), 9);
+}
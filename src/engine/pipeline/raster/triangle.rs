@@ -1,4 +1,5 @@
 use crate::engine::*;
+use std::ops::Range;
 
 #[derive(Clone)]
 pub struct Triangle<'a> {
@@ -13,38 +14,240 @@ impl Triangle<'_> {
     }
 }
 
-pub fn raster_triangle(a: &Vec2, b: &Vec2, c: &Vec2) -> Vec<((usize, usize), (f64, f64, f64))> {
+/**
+ * vertices are snapped to 1/256 of a pixel before the edge functions are set up,
+ * the same 8 bits of subpixel precision most gpus use
+ */
+pub const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL: i64 = 1 << SUBPIXEL_BITS;
+/**
+ * blocks of TILE_SIZE x TILE_SIZE pixels are rejected or accepted as a whole
+ */
+pub const TILE_SIZE: usize = 8;
+
+/**
+ * e(p) = a * x + b * y + c in fixed point, positive on the inner side of the edge p0 -> p1
+ */
+struct EdgeFunction {
+    a: i64,
+    b: i64,
+    c: i64,
+    bias: i64,
+}
+
+impl EdgeFunction {
+    fn new(p0: (i64, i64), p1: (i64, i64)) -> EdgeFunction {
+        let (a, b) = (p0.1 - p1.1, p1.0 - p0.0);
+        // top-left rule: a pixel center exactly on an edge belongs to the triangle only if the edge
+        // is a left edge or a horizontal top edge, so triangles sharing the edge draw it once
+        let is_top_left = a > 0 || (a == 0 && b > 0);
+        EdgeFunction {
+            a,
+            b,
+            c: p0.0 * p1.1 - p0.1 * p1.0,
+            bias: if is_top_left { 0 } else { -1 },
+        }
+    }
+    fn value(&self, p: (i64, i64)) -> i64 {
+        self.a * p.0 + self.b * p.1 + self.c
+    }
+    /**
+     * the value at the center of pixel (x, y)
+     */
+    fn at(&self, x: i64, y: i64) -> i64 {
+        self.value((x * SUBPIXEL + SUBPIXEL / 2, y * SUBPIXEL + SUBPIXEL / 2))
+    }
+    fn covers(&self, value: i64) -> bool {
+        value + self.bias >= 0
+    }
+}
+
+/**
+ * the pixels whose centers are covered by the triangle, with the barycentric weights of a, b and c there.
+ * either winding works, culling is left to the caller. the pipeline rasterizes samples, this is for the tests
+ */
+#[cfg(test)]
+pub fn raster_triangle(a: &Vec2, b: &Vec2, c: &Vec2) -> Vec<PixelCoverage> {
+    raster_triangle_in(a, b, c, 0..usize::MAX, 0..usize::MAX)
+}
+
+/**
+ * `raster_triangle` limited to the pixels in `x` and `y`, like a bin of the frame
+ */
+#[cfg(test)]
+pub fn raster_triangle_in(
+    a: &Vec2,
+    b: &Vec2,
+    c: &Vec2,
+    x: Range<usize>,
+    y: Range<usize>,
+) -> Vec<PixelCoverage> {
+    raster_triangle_samples(a, b, c, x, y, sample_pattern(1))
+        .into_iter()
+        .map(|(coord, _, weights)| (coord, weights))
+        .collect()
+}
+
+/**
+ * the standard sample positions of 1, 2, 4 and 8x multisampling, in pixels from the pixel center
+ */
+pub fn sample_pattern(samples: usize) -> &'static [(f64, f64)] {
+    match samples {
+        1 => &[(0.0, 0.0)],
+        2 => &[(0.25, 0.25), (-0.25, -0.25)],
+        4 => &[
+            (-0.125, -0.375),
+            (0.375, -0.125),
+            (-0.375, 0.125),
+            (0.125, 0.375),
+        ],
+        8 => &[
+            (0.0625, -0.1875),
+            (-0.0625, 0.1875),
+            (0.3125, 0.0625),
+            (-0.1875, -0.3125),
+            (-0.3125, 0.3125),
+            (-0.4375, -0.0625),
+            (0.1875, 0.4375),
+            (0.4375, -0.4375),
+        ],
+        _ => panic!("{} samples per pixel are not supported", samples),
+    }
+}
+
+/**
+ * a covered pixel and the weights of its center
+ */
+#[cfg(test)]
+pub type PixelCoverage = ((usize, usize), (f64, f64, f64));
+
+/**
+ * a pixel, the mask of the samples covered in it and the weights of its center
+ */
+pub type SampleCoverage = ((usize, usize), u32, (f64, f64, f64));
+
+/**
+ * the pixels in `x` and `y` where the triangle covers at least one of the sample positions `samples`.
+ * bit i of the mask is set when sample i is covered
+ */
+pub fn raster_triangle_samples(
+    a: &Vec2,
+    b: &Vec2,
+    c: &Vec2,
+    x: Range<usize>,
+    y: Range<usize>,
+    samples: &[(f64, f64)],
+) -> Vec<SampleCoverage> {
     let mut points = vec![];
-    let (x_min_f, x_max_f) = { get_min_and_max(a.x(), b.x(), c.x()) };
-    let (y_min_f, y_max_f) = { get_min_and_max(a.y(), b.y(), c.y()) };
-
-    let (x_min, y_min, x_max, y_max) = (
-        x_min_f.floor() as usize,
-        y_min_f.floor() as usize,
-        x_max_f.ceil() as usize,
-        y_max_f.ceil() as usize,
+    let snap = |v: (f64, f64)| {
+        (
+            (v.0 * SUBPIXEL as f64).round() as i64,
+            (v.1 * SUBPIXEL as f64).round() as i64,
+        )
+    };
+    let (p0, mut p1, mut p2) = (
+        snap((a.x(), a.y())),
+        snap((b.x(), b.y())),
+        snap((c.x(), c.y())),
     );
+    let mut area = EdgeFunction::new(p0, p1).value(p2);
+    let flipped = area < 0;
+    if flipped {
+        std::mem::swap(&mut p1, &mut p2);
+        area = -area;
+    }
+    if area == 0 {
+        return points;
+    }
+    // edges[i] is opposite vertex i, its value over the area is the weight of that vertex
+    let edges = [
+        EdgeFunction::new(p1, p2),
+        EdgeFunction::new(p2, p0),
+        EdgeFunction::new(p0, p1),
+    ];
+    let offsets: Vec<(i64, i64)> = samples.iter().map(|offset| snap(*offset)).collect();
+    // how far a sample is from its pixel center at most
+    let reach = offsets
+        .iter()
+        .fold(0, |reach, (ox, oy)| reach.max(ox.abs()).max(oy.abs()));
+    let full_mask = (1u32 << samples.len()) - 1;
+
+    // the pixels with samples inside the bounding box
+    let (x_min_fixed, x_max_fixed) = get_min_and_max(p0.0, p1.0, p2.0);
+    let (y_min_fixed, y_max_fixed) = get_min_and_max(p0.1, p1.1, p2.1);
+    let first = |min: i64| {
+        (min - SUBPIXEL / 2 - reach + SUBPIXEL - 1)
+            .div_euclid(SUBPIXEL)
+            .max(0)
+    };
+    let last = |max: i64| (max - SUBPIXEL / 2 + reach).div_euclid(SUBPIXEL);
+    let end = |range: &Range<usize>| range.end.min(i64::MAX as usize) as i64 - 1;
+    let x_min = first(x_min_fixed).max(x.start as i64);
+    let x_max = last(x_max_fixed).min(end(&x));
+    let y_min = first(y_min_fixed).max(y.start as i64);
+    let y_max = last(y_max_fixed).min(end(&y));
+    if x_min > x_max || y_min > y_max {
+        return points;
+    }
 
-    let mut has_entered_triangle_row = false;
-    let mut has_entered_triangle_column = false;
-
-    'row: for y in y_min..=y_max {
-        'column: for x in x_min..=x_max {
-            if let Some(bary_centric_params) = compute_bary_centric(x, y, a, b, c) {
-                points.push(((x, y), bary_centric_params));
-                has_entered_triangle_row = true;
-                has_entered_triangle_column = true;
-            } else if has_entered_triangle_column {
-                //说明遇到三角形的像素之后, 第一次没有遇到三角形的像素, 那么这一行之后也不会有三角形的像素了.
-                break 'column;
+    let tile = TILE_SIZE as i64;
+    let area = area as f64;
+    for tile_y in (y_min / tile * tile..=y_max).step_by(TILE_SIZE) {
+        for tile_x in (x_min / tile * tile..=x_max).step_by(TILE_SIZE) {
+            let (x0, x1) = (tile_x.max(x_min), (tile_x + tile - 1).min(x_max));
+            let (y0, y1) = (tile_y.max(y_min), (tile_y + tile - 1).min(y_max));
+            // the edge functions are linear, the corner pixels bound every pixel of the tile.
+            // samples off the center can be up to `slack` further in or out
+            let mut accepted = true;
+            let mut rejected = false;
+            for edge in edges.iter() {
+                let slack = (edge.a.abs() + edge.b.abs()) * reach;
+                let corners = [
+                    edge.at(x0, y0),
+                    edge.at(x1, y0),
+                    edge.at(x0, y1),
+                    edge.at(x1, y1),
+                ];
+                accepted &= corners.iter().all(|v| edge.covers(v - slack));
+                rejected |= !corners.iter().any(|v| edge.covers(v + slack));
+            }
+            if rejected {
+                continue;
+            }
+            for y in y0..=y1 {
+                let mut w = [edges[0].at(x0, y), edges[1].at(x0, y), edges[2].at(x0, y)];
+                for x in x0..=x1 {
+                    let mask = if accepted {
+                        full_mask
+                    } else {
+                        offsets.iter().enumerate().fold(0, |mask, (i, (ox, oy))| {
+                            let covered = edges
+                                .iter()
+                                .zip(w.iter())
+                                .all(|(e, v)| e.covers(v + e.a * ox + e.b * oy));
+                            if covered {
+                                mask | 1 << i
+                            } else {
+                                mask
+                            }
+                        })
+                    };
+                    if mask != 0 {
+                        let weights = (w[0] as f64 / area, w[1] as f64 / area, w[2] as f64 / area);
+                        let weights = if flipped {
+                            (weights.0, weights.2, weights.1)
+                        } else {
+                            weights
+                        };
+                        points.push(((x as usize, y as usize), mask, weights));
+                    }
+                    // one pixel to the right adds a once per subpixel
+                    for (v, edge) in w.iter_mut().zip(edges.iter()) {
+                        *v += edge.a * SUBPIXEL;
+                    }
+                }
             }
         }
-        if has_entered_triangle_row && !has_entered_triangle_column {
-            //说明遇到三角形的像素之后, 整整一行都没有遇到三角形的像素了, 那么整个扫描后面也不会有三角形的像素了.
-            break 'row;
-        }
-        has_entered_triangle_column = false;
-        has_entered_triangle_row = false;
     }
 
     points
@@ -67,50 +270,6 @@ fn get_min_and_max<'a, T: PartialOrd + Copy>(a: T, b: T, c: T) -> (T, T) {
     res
 }
 
-fn compute_bary_centric<'a>(
-    x: usize,
-    y: usize,
-    p0: &Vec2,
-    p1: &Vec2,
-    p2: &Vec2,
-) -> Option<(f64, f64, f64)> {
-    let p = Vec2::new(x as f64, y as f64);
-    let v0 = p1 - p0;
-    let v1 = p2 - p0;
-    let v2 = &p - p0;
-
-    let dot_00 = Vec2::dot(&v0, &v0);
-    let dot_01 = Vec2::dot(&v0, &v1);
-    let dot_02 = Vec2::dot(&v0, &v2);
-    let dot_11 = Vec2::dot(&v1, &v1);
-    let dot_12 = Vec2::dot(&v1, &v2);
-
-    let div = dot_00 * dot_11 - dot_01 * dot_01;
-    if div == 0.0 {
-        return None;
-    }
-
-    const EPSILON: f64 = -1e-9;
-
-    let inv_divider = 1.0 / div;
-    let alpha = (dot_11 * dot_02 - dot_01 * dot_12) * inv_divider;
-
-    if alpha < EPSILON {
-        return None;
-    }
-
-    let beta = (dot_00 * dot_12 - dot_01 * dot_02) * inv_divider;
-    if beta < EPSILON {
-        return None;
-    }
-    let gamma = 1.0 - alpha - beta;
-    if gamma < EPSILON {
-        return None;
-    }
-
-    Some((alpha, beta, gamma))
-}
-
 #[test]
 fn test_raster_triangle() {
     let a = &Vec2::new(0.0, 30.0);
@@ -120,8 +279,111 @@ fn test_raster_triangle() {
 
     assert_eq!(
         points.len(),
-        641,
-        "points should have area of 641, but got \n{}",
+        600,
+        "points should have area of 600, but got \n{}",
         crate::printer::render_points_to_string(points)
     );
+
+    // the top and the left edge go through pixel centers and are drawn, the diagonal is not
+    let a = &Vec2::new(0.5, 0.5);
+    let b = &Vec2::new(4.5, 0.5);
+    let c = &Vec2::new(0.5, 4.5);
+    let points = raster_triangle(a, b, c);
+    assert_eq!(points.len(), 10);
+    assert!(points.iter().all(|((x, y), _)| x + y < 4));
+    assert_eq!(points[0], ((0, 0), (1.0, 0.0, 0.0)));
+    // the other winding covers the same pixels with the same weights
+    assert_eq!(raster_triangle(a, c, b).len(), 10);
+    assert!(points.contains(&((3, 0), (0.25, 0.75, 0.0))));
+    assert!(raster_triangle(a, c, b).contains(&((3, 0), (0.25, 0.0, 0.75))));
+}
+
+#[test]
+fn test_raster_watertight() {
+    // the outline of a 32 x 32 square with vertices off the pixel grid
+    let stops = [0.0, 3.7, 11.25, 16.5, 20.0, 27.9];
+    let mut ring = vec![];
+    ring.extend(stops.iter().map(|t| Vec2::new(*t, 0.0)));
+    ring.extend(stops.iter().map(|t| Vec2::new(32.0, *t)));
+    ring.extend(stops.iter().map(|t| Vec2::new(32.0 - *t, 32.0)));
+    ring.extend(stops.iter().map(|t| Vec2::new(0.0, 32.0 - *t)));
+
+    // fans around a point off the grid and around a pixel center, whose spokes run through pixel centers
+    for center in [Vec2::new(13.37, 19.5), Vec2::new(16.5, 16.5)].iter() {
+        let mut coverage = vec![0; 32 * 32];
+        for i in 0..ring.len() {
+            let next = &ring[(i + 1) % ring.len()];
+            for ((x, y), (alpha, beta, gamma)) in raster_triangle(center, &ring[i], next) {
+                assert!((alpha + beta + gamma - 1.0).abs() < 1e-9);
+                coverage[y * 32 + x] += 1;
+            }
+        }
+        assert!(
+            coverage.iter().all(|count| *count == 1),
+            "every pixel should be drawn once, got {:?}",
+            coverage
+        );
+    }
+}
+
+#[test]
+fn test_raster_triangle_weights() {
+    let (a, b, c) = (
+        &Vec2::new(2.0, 1.0),
+        &Vec2::new(19.5, 3.5),
+        &Vec2::new(6.0, 15.0),
+    );
+    // the position of the pixel center is linear across the triangle, the weights of the vertices rebuild it
+    for ((x, y), (alpha, beta, gamma)) in raster_triangle(a, b, c) {
+        let u = a.x() * alpha + b.x() * beta + c.x() * gamma;
+        let v = a.y() * alpha + b.y() * beta + c.y() * gamma;
+        assert!((u - x as f64 - 0.5).abs() < 1e-9 && (v - y as f64 - 0.5).abs() < 1e-9);
+    }
+    // the weight of b peaks at the pixels around b
+    let points = raster_triangle(a, b, c);
+    let ((x, y), _) = points
+        .iter()
+        .max_by(|(_, p), (_, q)| p.1.partial_cmp(&q.1).unwrap())
+        .unwrap();
+    assert!((*x as f64 + 0.5 - b.x()).abs() < 1.5 && (*y as f64 + 0.5 - b.y()).abs() < 1.5);
+}
+
+#[test]
+fn test_raster_triangle_samples() {
+    // a vertical edge at x = 2.5 splits the pixels of column 2 between the sample positions
+    let (a, b, c) = (
+        &Vec2::new(2.5, 0.0),
+        &Vec2::new(8.0, 0.0),
+        &Vec2::new(2.5, 8.0),
+    );
+    let points = raster_triangle_samples(a, b, c, 0..4, 3..4, sample_pattern(4));
+    let masks: Vec<((usize, usize), u32)> = points.iter().map(|(p, m, _)| (*p, *m)).collect();
+    // the samples right of the center are the second and the fourth
+    assert_eq!(masks, vec![((2, 3), 0b1010), ((3, 3), 0b1111)]);
+
+    // every sample of the square is covered once by the fan, like the pixel centers
+    let ring = [
+        Vec2::new(0.0, 0.0),
+        Vec2::new(13.3, 0.0),
+        Vec2::new(16.0, 0.0),
+        Vec2::new(16.0, 9.7),
+        Vec2::new(16.0, 16.0),
+        Vec2::new(4.25, 16.0),
+        Vec2::new(0.0, 16.0),
+    ];
+    let center = Vec2::new(7.5, 8.5);
+    let mut coverage = vec![[0; 8]; 16 * 16];
+    for i in 0..ring.len() {
+        let next = &ring[(i + 1) % ring.len()];
+        for ((x, y), mask, _) in
+            raster_triangle_samples(&center, &ring[i], next, 0..16, 0..16, sample_pattern(8))
+        {
+            for (sample, count) in coverage[y * 16 + x].iter_mut().enumerate() {
+                *count += (mask >> sample) & 1;
+            }
+        }
+    }
+    assert!(coverage
+        .iter()
+        .all(|counts| counts.iter().all(|count| *count == 1)));
 }
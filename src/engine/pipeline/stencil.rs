@@ -0,0 +1,159 @@
+use super::depth::CompareFunc;
+
+/**
+ * what happens to the stored stencil value, `Incr` and `Decr` clamp while the wrap ops wrap around
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    Incr,
+    IncrWrap,
+    Decr,
+    DecrWrap,
+    Invert,
+}
+
+impl StencilOp {
+    pub fn apply(self, stored: u8, reference: u8) -> u8 {
+        match self {
+            StencilOp::Keep => stored,
+            StencilOp::Zero => 0,
+            StencilOp::Replace => reference,
+            StencilOp::Incr => stored.saturating_add(1),
+            StencilOp::IncrWrap => stored.wrapping_add(1),
+            StencilOp::Decr => stored.saturating_sub(1),
+            StencilOp::DecrWrap => stored.wrapping_sub(1),
+            StencilOp::Invert => !stored,
+        }
+    }
+}
+
+/**
+ * the stencil test of one side of the triangles. the test passes when
+ * `reference & read_mask <func> stored & read_mask` holds
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StencilFace {
+    pub func: CompareFunc,
+    pub reference: u8,
+    pub read_mask: u8,
+    /**
+     * the bits the ops may change
+     */
+    pub write_mask: u8,
+    /**
+     * applied when the stencil test fails
+     */
+    pub fail: StencilOp,
+    /**
+     * applied when the stencil test passes and the depth test fails
+     */
+    pub depth_fail: StencilOp,
+    /**
+     * applied when both tests pass
+     */
+    pub pass: StencilOp,
+}
+
+impl Default for StencilFace {
+    fn default() -> StencilFace {
+        StencilFace {
+            func: CompareFunc::Always,
+            reference: 0,
+            read_mask: 0xff,
+            write_mask: 0xff,
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Keep,
+        }
+    }
+}
+
+impl StencilFace {
+    pub fn test(&self, stored: u8) -> bool {
+        self.func
+            .test(self.reference & self.read_mask, stored & self.read_mask)
+    }
+    /**
+     * the value to store after `op`, bits outside the write mask keep their value
+     */
+    pub fn update(&self, op: StencilOp, stored: u8) -> u8 {
+        let value = op.apply(stored, self.reference);
+        (stored & !self.write_mask) | (value & self.write_mask)
+    }
+}
+
+/**
+ * the stencil buffer settings of a `Context`. front faces are clockwise in ndc,
+ * lines and points use `front`
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct StencilState {
+    /**
+     * a disabled stencil test always passes and leaves the buffer as it is
+     */
+    pub enabled: bool,
+    pub front: StencilFace,
+    pub back: StencilFace,
+    pub clear_value: u8,
+}
+
+impl StencilState {
+    /**
+     * the same test and ops for both faces
+     */
+    pub fn both(face: StencilFace) -> StencilState {
+        StencilState {
+            enabled: true,
+            front: face,
+            back: face,
+            clear_value: 0,
+        }
+    }
+    /**
+     * the face to test, `None` when the test is disabled
+     */
+    pub fn face(&self, front_facing: bool) -> Option<&StencilFace> {
+        match (self.enabled, front_facing) {
+            (false, _) => None,
+            (true, true) => Some(&self.front),
+            (true, false) => Some(&self.back),
+        }
+    }
+}
+
+#[test]
+fn test_stencil_state() {
+    assert_eq!(StencilOp::Incr.apply(255, 0), 255);
+    assert_eq!(StencilOp::IncrWrap.apply(255, 0), 0);
+    assert_eq!(StencilOp::Decr.apply(0, 0), 0);
+    assert_eq!(StencilOp::DecrWrap.apply(0, 0), 255);
+    assert_eq!(StencilOp::Replace.apply(3, 7), 7);
+    assert_eq!(StencilOp::Invert.apply(0b1010_0000, 0), 0b0101_1111);
+
+    let face = StencilFace {
+        func: CompareFunc::Equal,
+        reference: 0b0001_0001,
+        read_mask: 0b0000_1111,
+        write_mask: 0b0000_1111,
+        ..StencilFace::default()
+    };
+    // only the low bits are compared and written
+    assert!(face.test(0b1110_0001));
+    assert!(!face.test(0b0000_0010));
+    assert_eq!(face.update(StencilOp::Zero, 0b1111_1111), 0b1111_0000);
+    assert_eq!(face.update(StencilOp::Replace, 0b1010_0000), 0b1010_0001);
+
+    let state = StencilState {
+        back: StencilFace {
+            pass: StencilOp::DecrWrap,
+            ..face
+        },
+        ..StencilState::both(face)
+    };
+    assert_eq!(state.face(true), Some(&face));
+    assert_eq!(state.face(false).unwrap().pass, StencilOp::DecrWrap);
+    assert_eq!(StencilState::default().face(true), None);
+}
@@ -0,0 +1,101 @@
+use std::ops::Range;
+
+/**
+ * the rectangle of the frame ndc is mapped to, in pixels from the top left corner.
+ * ndc y points up and frame rows go down, so +1 lands on the top edge of the rectangle.
+ * window depth in [0, 1] is squeezed into [min_depth, max_depth]
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    pub min_depth: f64,
+    pub max_depth: f64,
+}
+
+impl Viewport {
+    /**
+     * the whole of a `width` by `height` frame with the full depth range
+     */
+    pub fn full(width: usize, height: usize) -> Viewport {
+        Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: width as f64,
+            height: height as f64,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }
+    }
+    /**
+     * the pixel position of ndc `x` and `y`
+     */
+    pub fn to_pixel(self, x: f64, y: f64) -> (f64, f64) {
+        (
+            self.x + (x + 1.0) / 2.0 * self.width,
+            self.y + (1.0 - y) / 2.0 * self.height,
+        )
+    }
+    /**
+     * `depth` in [0, 1] to the depth range of the viewport
+     */
+    pub fn depth(&self, depth: f64) -> f64 {
+        self.min_depth + (self.max_depth - self.min_depth) * depth
+    }
+}
+
+/**
+ * fragments outside the rectangle are dropped before any test, clears only touch what is inside.
+ * in pixels from the top left corner, like the viewport
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scissor {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Scissor {
+    /**
+     * the columns and rows of a `width` by `height` frame inside the rectangle
+     */
+    pub fn ranges(&self, width: usize, height: usize) -> (Range<usize>, Range<usize>) {
+        let clamp = |start: usize, len: usize, size: usize| {
+            start.min(size)..start.saturating_add(len).min(size)
+        };
+        (
+            clamp(self.x, self.width, width),
+            clamp(self.y, self.height, height),
+        )
+    }
+}
+
+#[test]
+fn test_viewport() {
+    let full = Viewport::full(8, 4);
+    assert_eq!(full.to_pixel(-1.0, 1.0), (0.0, 0.0));
+    assert_eq!(full.to_pixel(1.0, -1.0), (8.0, 4.0));
+
+    // the right half, drawn in the back half of the depth buffer
+    let right = Viewport {
+        x: 4.0,
+        width: 4.0,
+        min_depth: 0.5,
+        ..full
+    };
+    assert_eq!(right.to_pixel(-1.0, 1.0), (4.0, 0.0));
+    assert_eq!(right.to_pixel(0.0, 0.0), (6.0, 2.0));
+    assert_eq!((right.depth(0.0), right.depth(1.0)), (0.5, 1.0));
+
+    let scissor = Scissor {
+        x: 6,
+        y: 1,
+        width: 10,
+        height: 2,
+    };
+    assert_eq!(scissor.ranges(8, 4), (6..8, 1..3));
+    assert_eq!(scissor.ranges(4, 4).0, 4..4);
+}
@@ -1,16 +1,162 @@
 use super::base::*;
+use super::buffer::VertexLayout;
+use super::shader::{Barycentric, Shader, Varying};
+use super::texture::Texture;
+use std::sync::Arc;
 
+/**
+ * the varyings and uniforms of a fragment and its pixel to an rgba color
+ */
+pub type FragmentShader =
+    Box<dyn Fn(&Vec<ShaderData>, &Vec<ShaderData>, Vec2) -> Vec4 + Send + Sync>;
+
+/**
+ * a shader put together at runtime. attributes are read from the buffers of the `Context`
+ * and reach the fragment shader as varyings, uniforms are matched by position
+ */
 pub struct Program {
-    pub vertex_shader: Box<dyn Fn(&Vec<ShaderData>, &Vec<ShaderData>, Vec4) -> Vec4>,
-    pub fragment_shader: Box<dyn Fn(&Vec<ShaderData>, &Vec<ShaderData>, Vec2) -> Vec4>,
+    pub vertex_shader: Box<dyn Fn(&Vec<ShaderData>, &Vec<ShaderData>, Vec4) -> Vec4 + Send + Sync>,
+    pub fragment_shader: FragmentShader,
+    /**
+     * one shader per attachment of the frame, like gl_FragData[1..]. they run after `fragment_shader`
+     * with the same inputs
+     */
+    pub outputs: Vec<FragmentShader>,
     pub attributes: Vec<Attribute>,
     pub uniforms: Vec<Uniform>,
 }
 
+impl Default for Program {
+    /**
+     * passes positions through and draws black
+     */
+    fn default() -> Program {
+        Program {
+            vertex_shader: Box::new(|_, _, position| position),
+            fragment_shader: Box::new(|_, _, _| Vec4::new(0.0, 0.0, 0.0, 1.0)),
+            outputs: vec![],
+            attributes: vec![],
+            uniforms: vec![],
+        }
+    }
+}
+
+impl Shader for Program {
+    /**
+     * the position and the attribute values
+     */
+    type Vertex = (Vec4, Vec<ShaderData>);
+    type Varying = Vec<ShaderData>;
+    type Uniforms = Vec<ShaderData>;
+    const DISCARDS: bool = false;
+
+    fn vertex(&self, vertex: &Self::Vertex, uniforms: &Self::Uniforms) -> (Vec4, Self::Varying) {
+        let (position, attributes) = vertex;
+        (
+            (self.vertex_shader)(attributes, uniforms, *position),
+            attributes.clone(),
+        )
+    }
+    fn fragment(
+        &self,
+        varying: &Self::Varying,
+        uniforms: &Self::Uniforms,
+        coord: Vec2,
+    ) -> Option<Vec4> {
+        Some((self.fragment_shader)(varying, uniforms, coord))
+    }
+    fn fragment_outputs(
+        &self,
+        varying: &Self::Varying,
+        uniforms: &Self::Uniforms,
+        coord: Vec2,
+    ) -> Option<(Vec4, Vec<Vec4>)> {
+        let color = (self.fragment_shader)(varying, uniforms, coord);
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|output| output(varying, uniforms, coord))
+            .collect();
+        Some((color, outputs))
+    }
+    fn interpolate(
+        &self,
+        vertices: [&Self::Varying; 3],
+        provoking: &Self::Varying,
+        weights: &Barycentric,
+    ) -> Self::Varying {
+        let qualifiers: Vec<Interpolation> = self
+            .attributes
+            .iter()
+            .map(|attr| attr.interpolation)
+            .collect();
+        interpolate_attribute(vertices, provoking, weights, &qualifiers)
+    }
+}
+
+impl Varying for Vec<ShaderData> {
+    fn interpolate(vertices: [&Self; 3], provoking: &Self, weights: &Barycentric) -> Self {
+        interpolate_attribute(vertices, provoking, weights, &[])
+    }
+}
+
+/**
+ * blends each attribute by its qualifier, missing qualifiers are smooth
+ */
+fn interpolate_attribute(
+    attrs: [&Vec<ShaderData>; 3],
+    provoking: &[ShaderData],
+    weights: &Barycentric,
+    qualifiers: &[Interpolation],
+) -> Vec<ShaderData> {
+    let mut res = Vec::with_capacity(provoking.len());
+    for i in 0..provoking.len() {
+        let qualifier = qualifiers.get(i).copied().unwrap_or_default();
+        if qualifier == Interpolation::Flat || !provoking[i].interpolates() {
+            res.push(provoking[i].clone());
+            continue;
+        }
+        let [alpha, beta, gamma] = match qualifier {
+            Interpolation::NoPerspective => weights.screen,
+            _ => weights.perspective,
+        };
+        let a_attr = &attrs[0][i] * alpha;
+        let b_attr = &attrs[1][i] * beta;
+        let c_attr = &attrs[2][i] * gamma;
+        res.push(a_attr + b_attr + c_attr);
+    }
+    res
+}
+
 #[derive(Clone)]
 pub struct Attribute {
     pub index: usize,
     pub name: String,
+    pub interpolation: Interpolation,
+    /**
+     * where the attribute is in its buffer, None uses the layout of the buffer
+     */
+    pub layout: Option<VertexLayout>,
+}
+
+/**
+ * how a varying is interpolated across a triangle, like the glsl qualifiers
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Interpolation {
+    /**
+     * perspective correct
+     */
+    #[default]
+    Smooth,
+    /**
+     * linear in screen space
+     */
+    NoPerspective,
+    /**
+     * every fragment gets the value of the last vertex of the triangle
+     */
+    Flat,
 }
 
 pub struct Uniform {
@@ -21,17 +167,48 @@ pub struct Uniform {
 #[derive(Clone, Debug)]
 pub enum ShaderData {
     Float(f64),
+    /**
+     * never interpolated, every fragment gets the value of the last vertex like a flat varying
+     */
+    Int(i64),
+    Vec2(Vec2),
+    Vec3(Vec3),
     Vec4(Vec4),
+    Mat3(Mat3),
     Mat4(Mat4),
+    /**
+     * a texture for the fragment shader to sample, shared rather than copied per pixel
+     */
+    Sampler(Arc<Texture>),
 }
 
+impl ShaderData {
+    /**
+     * whether varyings of this type are blended between vertices, the rest are flat
+     */
+    pub fn interpolates(&self) -> bool {
+        !matches!(self, ShaderData::Int(_) | ShaderData::Sampler(_))
+    }
+}
+
+// varyings are blended component by component, w included. the homogeneous operators of Vec4 would
+// divide colors by their alpha.
 impl std::ops::Mul<f64> for &ShaderData {
     type Output = ShaderData;
     fn mul(self, rhs: f64) -> ShaderData {
         match self {
             ShaderData::Float(v) => ShaderData::Float(*v * rhs),
-            ShaderData::Vec4(v) => ShaderData::Vec4(*v * rhs),
+            ShaderData::Int(v) => ShaderData::Int(*v),
+            ShaderData::Vec2(v) => ShaderData::Vec2(Vec2 {
+                value: v.value.map(|c| c * rhs),
+            }),
+            ShaderData::Vec3(v) => ShaderData::Vec3(*v * rhs),
+            ShaderData::Vec4(v) => ShaderData::Vec4(Vec4 {
+                value: v.value.map(|c| c * rhs),
+            }),
+            ShaderData::Mat3(v) => ShaderData::Mat3(*v * rhs),
             ShaderData::Mat4(v) => ShaderData::Mat4(*v * rhs),
+            ShaderData::Sampler(v) => ShaderData::Sampler(v.clone()),
         }
     }
 }
@@ -41,9 +218,88 @@ impl std::ops::Add<ShaderData> for ShaderData {
     fn add(self, rhs: ShaderData) -> ShaderData {
         match (self, rhs) {
             (ShaderData::Float(v), ShaderData::Float(rhs)) => ShaderData::Float(v + rhs),
-            (ShaderData::Vec4(v), ShaderData::Vec4(rhs)) => ShaderData::Vec4(v + rhs),
+            (ShaderData::Vec2(v), ShaderData::Vec2(rhs)) => {
+                ShaderData::Vec2(Vec2::new(v.x() + rhs.x(), v.y() + rhs.y()))
+            }
+            (ShaderData::Vec3(v), ShaderData::Vec3(rhs)) => ShaderData::Vec3(v + rhs),
+            (ShaderData::Vec4(mut v), ShaderData::Vec4(rhs)) => {
+                for (c, r) in v.value.iter_mut().zip(rhs.value.iter()) {
+                    *c += r;
+                }
+                ShaderData::Vec4(v)
+            }
+            (ShaderData::Mat3(v), ShaderData::Mat3(rhs)) => ShaderData::Mat3(v + rhs),
             (ShaderData::Mat4(v), ShaderData::Mat4(rhs)) => ShaderData::Mat4(v + rhs),
             (left, _) => left,
         }
     }
 }
+
+#[test]
+fn test_shader_data_interpolation() {
+    let blend = |a: &ShaderData, b: &ShaderData| a * 0.75 + b * 0.25;
+
+    // a translucent color keeps its alpha and is not divided by it
+    let a = ShaderData::Vec4(Vec4::new(1.0, 0.0, 0.0, 0.5));
+    let b = ShaderData::Vec4(Vec4::new(0.0, 1.0, 0.0, 1.0));
+    match blend(&a, &b) {
+        ShaderData::Vec4(v) => assert_eq!(v.value, [0.75, 0.25, 0.0, 0.625]),
+        other => panic!("{:?}", other),
+    }
+    match blend(
+        &ShaderData::Vec2(Vec2::new(0.0, 4.0)),
+        &ShaderData::Vec2(Vec2::new(4.0, 0.0)),
+    ) {
+        ShaderData::Vec2(v) => assert_eq!((v.x(), v.y()), (1.0, 3.0)),
+        other => panic!("{:?}", other),
+    }
+    match blend(
+        &ShaderData::Vec3(Vec3::new(0.0, 4.0, 8.0)),
+        &ShaderData::Vec3(Vec3::WHITE),
+    ) {
+        ShaderData::Vec3(v) => assert_eq!(v.value, [0.25, 3.25, 6.25]),
+        other => panic!("{:?}", other),
+    }
+    match blend(
+        &ShaderData::Mat3(Mat3::IDENTITY),
+        &ShaderData::Mat3(Mat3::IDENTITY * 5.0),
+    ) {
+        ShaderData::Mat3(m) => assert_eq!(m.value[4], 2.0),
+        other => panic!("{:?}", other),
+    }
+    assert!(!ShaderData::Int(3).interpolates() && ShaderData::Float(3.0).interpolates());
+}
+
+#[test]
+fn test_program_interpolation() {
+    let vertex = |v: f64| vec![ShaderData::Float(v); 3];
+    let (a, b, c) = (vertex(0.0), vertex(1.0), vertex(2.0));
+    let mut program = Program::default();
+    for interpolation in [
+        Interpolation::Smooth,
+        Interpolation::NoPerspective,
+        Interpolation::Flat,
+    ]
+    .iter()
+    {
+        program.attributes.push(Attribute {
+            index: 0,
+            name: format!("{:?}", interpolation),
+            interpolation: *interpolation,
+            layout: None,
+        });
+    }
+    // halfway between a vertex at w = 1 and one four times as far away
+    let weights = Barycentric::new([0.5, 0.5, 0.0], [1.0, 0.25, 1.0]);
+    let values: Vec<f64> = program
+        .interpolate([&a, &b, &c], &c, &weights)
+        .iter()
+        .map(|v| match v {
+            ShaderData::Float(v) => *v,
+            _ => panic!("the attribute changed its type"),
+        })
+        .collect();
+    assert!((values[0] - 0.2).abs() < 1e-9, "{:?}", values);
+    assert!((values[1] - 0.5).abs() < 1e-9, "{:?}", values);
+    assert_eq!(values[2], 2.0);
+}
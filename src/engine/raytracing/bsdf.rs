@@ -0,0 +1,584 @@
+use crate::Vec3;
+use std::f64::consts::PI;
+
+/**
+ * all directions are in the local shading frame: z is the shading normal, on the side of `wo`.
+ * `wo` points towards the viewer and `wi` towards the light, both away from the surface.
+ * `front_face` tells whether `wo` is outside the object, only dielectrics care about it.
+ */
+pub trait Bsdf {
+    /**
+     * the bsdf value f(wo, wi), not multiplied by the cosine. always 0 for perfectly specular lobes
+     */
+    fn eval(&self, wo: &Vec3, wi: &Vec3, front_face: bool) -> Vec3;
+    /**
+     * solid angle pdf of `sample` returning `wi`. always 0 for perfectly specular lobes
+     */
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, front_face: bool) -> f64;
+    /**
+     * importance samples `wi`, `u` are three uniform random numbers in [0, 1)
+     */
+    fn sample(&self, wo: &Vec3, front_face: bool, u: (f64, f64, f64)) -> Option<BsdfSample>;
+}
+
+#[derive(Debug)]
+pub struct BsdfSample {
+    pub wi: Vec3,
+    pub value: Vec3,
+    pub pdf: f64,
+    /**
+     * sampled from a delta distribution, `value` and `pdf` are only meaningful as a ratio
+     */
+    pub specular: bool,
+}
+
+impl BsdfSample {
+    /**
+     * value * cos / pdf, what the path throughput gets multiplied by
+     */
+    pub fn weight(&self) -> Vec3 {
+        self.value * (self.wi.z().abs() / self.pdf)
+    }
+}
+
+/**
+ * orthonormal basis around a normal, converts between world space and the local shading frame
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    pub normal: Vec3,
+}
+
+impl Onb {
+    /**
+     * Duff et al. 2017, "Building an Orthonormal Basis, Revisited"
+     */
+    pub fn from_normal(normal: &Vec3) -> Onb {
+        let sign = 1.0f64.copysign(normal.z());
+        let a = -1.0 / (sign + normal.z());
+        let b = normal.x() * normal.y() * a;
+        Onb {
+            tangent: Vec3::new(
+                1.0 + sign * normal.x() * normal.x() * a,
+                sign * b,
+                -sign * normal.x(),
+            ),
+            bitangent: Vec3::new(b, sign + normal.y() * normal.y() * a, -normal.y()),
+            normal: *normal,
+        }
+    }
+    pub fn to_local(self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            Vec3::dot(v, &self.tangent),
+            Vec3::dot(v, &self.bitangent),
+            Vec3::dot(v, &self.normal),
+        )
+    }
+    pub fn to_world(self, v: &Vec3) -> Vec3 {
+        self.tangent * v.x() + self.bitangent * v.y() + self.normal * v.z()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Lambertian {
+    pub albedo: Vec3,
+}
+
+/**
+ * GGX microfacet metal, roughness 0 is a perfect mirror
+ */
+#[derive(Debug, Clone)]
+pub struct Conductor {
+    /**
+     * reflectance at normal incidence, used in Schlick's approximation
+     */
+    pub f0: Vec3,
+    pub roughness: f64,
+}
+
+/**
+ * GGX microfacet glass (Walter et al. 2007), roughness 0 is perfectly smooth.
+ * transmitted radiance is not scaled by 1/eta^2, it cancels out anyway once a path leaves a closed object.
+ */
+#[derive(Debug, Clone)]
+pub struct Dielectric {
+    /**
+     * index of refraction of the inside, the outside is assumed to be vacuum
+     */
+    pub ior: f64,
+    pub roughness: f64,
+    pub tint: Vec3,
+}
+
+/**
+ * a dielectric specular coat over a diffuse base, blended towards metal by `metallic`
+ * and towards glass by `transmission`
+ */
+#[derive(Debug, Clone)]
+pub struct Principled {
+    pub base_color: Vec3,
+    pub metallic: f64,
+    pub roughness: f64,
+    /**
+     * reflectance at normal incidence of the coat, 0.04 for most non-metals
+     */
+    pub specular: f64,
+    pub transmission: f64,
+    pub ior: f64,
+}
+
+impl Bsdf for Lambertian {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, _front_face: bool) -> Vec3 {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Vec3::BLACK;
+        }
+        self.albedo / PI
+    }
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, _front_face: bool) -> f64 {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        wi.z() / PI
+    }
+    fn sample(&self, wo: &Vec3, front_face: bool, u: (f64, f64, f64)) -> Option<BsdfSample> {
+        let wi = cosine_hemisphere(u.0, u.1);
+        let pdf = self.pdf(wo, &wi, front_face);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            value: self.eval(wo, &wi, front_face),
+            pdf,
+            specular: false,
+        })
+    }
+}
+
+impl Bsdf for Conductor {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, _front_face: bool) -> Vec3 {
+        if self.roughness <= 0.0 || wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Vec3::BLACK;
+        }
+        let alpha = roughness_to_alpha(self.roughness);
+        let mut h = *wo + *wi;
+        h.normalize();
+        let f = fresnel_schlick(&self.f0, Vec3::dot(wo, &h));
+        f * (ggx_d(&h, alpha) * ggx_g(wo, wi, alpha) / (4.0 * wo.z() * wi.z()))
+    }
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, _front_face: bool) -> f64 {
+        if self.roughness <= 0.0 || wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let alpha = roughness_to_alpha(self.roughness);
+        let mut h = *wo + *wi;
+        h.normalize();
+        ggx_d(&h, alpha) * h.z() / (4.0 * Vec3::dot(wo, &h))
+    }
+    fn sample(&self, wo: &Vec3, front_face: bool, u: (f64, f64, f64)) -> Option<BsdfSample> {
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        if self.roughness <= 0.0 {
+            let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+            return Some(BsdfSample {
+                wi,
+                value: fresnel_schlick(&self.f0, wo.z()) / wo.z(),
+                pdf: 1.0,
+                specular: true,
+            });
+        }
+        let h = ggx_sample_h(roughness_to_alpha(self.roughness), u.0, u.1);
+        let wi = reflect(wo, &h);
+        let pdf = self.pdf(wo, &wi, front_face);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            value: self.eval(wo, &wi, front_face),
+            pdf,
+            specular: false,
+        })
+    }
+}
+
+impl Dielectric {
+    /**
+     * ior of the side `wi` would refract into over the side of `wo`
+     */
+    fn relative_ior(&self, front_face: bool) -> f64 {
+        if front_face {
+            self.ior
+        } else {
+            1.0 / self.ior
+        }
+    }
+    /**
+     * the half vector of a refraction, on the side of `wo`. None if `wo` and `wi` cannot be a refraction pair
+     */
+    fn refraction_half_vector(wo: &Vec3, wi: &Vec3, eta: f64) -> Option<Vec3> {
+        let mut h = *wo + *wi * eta;
+        if h.length() == 0.0 {
+            return None;
+        }
+        h.normalize();
+        if h.z() < 0.0 {
+            h = h * -1.0;
+        }
+        if Vec3::dot(wo, &h) <= 0.0 || Vec3::dot(wi, &h) >= 0.0 {
+            return None;
+        }
+        Some(h)
+    }
+}
+
+impl Bsdf for Dielectric {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, front_face: bool) -> Vec3 {
+        if self.roughness <= 0.0 || wo.z() <= 0.0 || wi.z() == 0.0 {
+            return Vec3::BLACK;
+        }
+        let alpha = roughness_to_alpha(self.roughness);
+        let eta = self.relative_ior(front_face);
+        if wi.z() > 0.0 {
+            let mut h = *wo + *wi;
+            h.normalize();
+            let f = fresnel_dielectric(Vec3::dot(wo, &h), eta);
+            let value = f * ggx_d(&h, alpha) * ggx_g(wo, wi, alpha) / (4.0 * wo.z() * wi.z());
+            return Vec3::WHITE * value;
+        }
+        let h = match Dielectric::refraction_half_vector(wo, wi, eta) {
+            Some(h) => h,
+            None => return Vec3::BLACK,
+        };
+        let wo_h = Vec3::dot(wo, &h);
+        let wi_h = Vec3::dot(wi, &h);
+        let f = fresnel_dielectric(wo_h, eta);
+        let denom = (wo_h + eta * wi_h) * (wo_h + eta * wi_h);
+        let value = eta * eta * (1.0 - f) * ggx_d(&h, alpha) * ggx_g(wo, wi, alpha) * wo_h * wi_h.abs()
+            / (wo.z() * wi.z().abs() * denom);
+        self.tint * value
+    }
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, front_face: bool) -> f64 {
+        if self.roughness <= 0.0 || wo.z() <= 0.0 || wi.z() == 0.0 {
+            return 0.0;
+        }
+        let alpha = roughness_to_alpha(self.roughness);
+        let eta = self.relative_ior(front_face);
+        if wi.z() > 0.0 {
+            let mut h = *wo + *wi;
+            h.normalize();
+            let wo_h = Vec3::dot(wo, &h);
+            let f = fresnel_dielectric(wo_h, eta);
+            return f * ggx_d(&h, alpha) * h.z() / (4.0 * wo_h);
+        }
+        let h = match Dielectric::refraction_half_vector(wo, wi, eta) {
+            Some(h) => h,
+            None => return 0.0,
+        };
+        let wo_h = Vec3::dot(wo, &h);
+        let wi_h = Vec3::dot(wi, &h);
+        let f = fresnel_dielectric(wo_h, eta);
+        let denom = (wo_h + eta * wi_h) * (wo_h + eta * wi_h);
+        (1.0 - f) * ggx_d(&h, alpha) * h.z() * eta * eta * wi_h.abs() / denom
+    }
+    fn sample(&self, wo: &Vec3, front_face: bool, u: (f64, f64, f64)) -> Option<BsdfSample> {
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let eta = self.relative_ior(front_face);
+        if self.roughness <= 0.0 {
+            let n = Vec3::new(0.0, 0.0, 1.0);
+            let f = fresnel_dielectric(wo.z(), eta);
+            if u.0 < f {
+                let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+                return Some(BsdfSample {
+                    wi,
+                    value: Vec3::WHITE * (f / wi.z()),
+                    pdf: f,
+                    specular: true,
+                });
+            }
+            let wi = refract(wo, &n, eta)?;
+            return Some(BsdfSample {
+                wi,
+                value: self.tint * ((1.0 - f) / wi.z().abs()),
+                pdf: 1.0 - f,
+                specular: true,
+            });
+        }
+        let h = ggx_sample_h(roughness_to_alpha(self.roughness), u.1, u.2);
+        let f = fresnel_dielectric(Vec3::dot(wo, &h), eta);
+        let wi = if u.0 < f {
+            reflect(wo, &h)
+        } else {
+            refract(wo, &h, eta)?
+        };
+        let pdf = self.pdf(wo, &wi, front_face);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            value: self.eval(wo, &wi, front_face),
+            pdf,
+            specular: false,
+        })
+    }
+}
+
+impl Principled {
+    /**
+     * the lobes and the probability of sampling each of them
+     */
+    fn lobes(&self, wo: &Vec3) -> (Lambertian, Conductor, Dielectric, [f64; 3]) {
+        let dielectric_f0 = Vec3::WHITE * self.specular;
+        let f0 = dielectric_f0 * (1.0 - self.metallic) + self.base_color * self.metallic;
+        // light that makes it through the coat reaches the diffuse base
+        let coat = fresnel_schlick(&dielectric_f0, wo.z().max(0.0));
+        let diffuse_weight = (1.0 - self.metallic) * (1.0 - self.transmission);
+        let transmission_weight = (1.0 - self.metallic) * self.transmission;
+        let diffuse = Lambertian {
+            albedo: Vec3::multiply(&self.base_color, &(Vec3::WHITE - coat)) * diffuse_weight,
+        };
+        let specular = Conductor {
+            f0,
+            roughness: self.roughness,
+        };
+        let glass = Dielectric {
+            ior: self.ior,
+            roughness: self.roughness,
+            tint: self.base_color,
+        };
+        let luminance = |c: &Vec3| (c.x() + c.y() + c.z()) / 3.0;
+        let weights = [
+            luminance(&diffuse.albedo),
+            luminance(&fresnel_schlick(&f0, wo.z().max(0.0))) * (1.0 - transmission_weight),
+            transmission_weight,
+        ];
+        let total: f64 = weights.iter().sum();
+        let probabilities = if total > 0.0 {
+            [weights[0] / total, weights[1] / total, weights[2] / total]
+        } else {
+            [1.0, 0.0, 0.0]
+        };
+        (diffuse, specular, glass, probabilities)
+    }
+}
+
+impl Bsdf for Principled {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, front_face: bool) -> Vec3 {
+        let (diffuse, specular, glass, _) = self.lobes(wo);
+        let transmission_weight = (1.0 - self.metallic) * self.transmission;
+        diffuse.eval(wo, wi, front_face)
+            + specular.eval(wo, wi, front_face) * (1.0 - transmission_weight)
+            + glass.eval(wo, wi, front_face) * transmission_weight
+    }
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, front_face: bool) -> f64 {
+        let (diffuse, specular, glass, p) = self.lobes(wo);
+        diffuse.pdf(wo, wi, front_face) * p[0]
+            + specular.pdf(wo, wi, front_face) * p[1]
+            + glass.pdf(wo, wi, front_face) * p[2]
+    }
+    fn sample(&self, wo: &Vec3, front_face: bool, u: (f64, f64, f64)) -> Option<BsdfSample> {
+        let (diffuse, specular, glass, p) = self.lobes(wo);
+        let transmission_weight = (1.0 - self.metallic) * self.transmission;
+        // reuse the first random number to pick the lobe
+        let (sample, probability, lobe_weight) = if u.0 < p[0] {
+            (diffuse.sample(wo, front_face, (u.0 / p[0], u.1, u.2))?, p[0], 1.0)
+        } else if u.0 < p[0] + p[1] {
+            let u0 = (u.0 - p[0]) / p[1];
+            (specular.sample(wo, front_face, (u0, u.1, u.2))?, p[1], 1.0 - transmission_weight)
+        } else {
+            let u0 = ((u.0 - p[0] - p[1]) / p[2]).min(1.0 - f64::EPSILON);
+            (glass.sample(wo, front_face, (u0, u.1, u.2))?, p[2], transmission_weight)
+        };
+        if sample.specular {
+            return Some(BsdfSample {
+                value: sample.value * lobe_weight,
+                pdf: sample.pdf * probability,
+                ..sample
+            });
+        }
+        let pdf = self.pdf(wo, &sample.wi, front_face);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            value: self.eval(wo, &sample.wi, front_face),
+            pdf,
+            ..sample
+        })
+    }
+}
+
+fn roughness_to_alpha(roughness: f64) -> f64 {
+    (roughness * roughness).max(1e-4)
+}
+
+fn ggx_d(h: &Vec3, alpha: f64) -> f64 {
+    if h.z() <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let t = h.z() * h.z() * (a2 - 1.0) + 1.0;
+    a2 / (PI * t * t)
+}
+
+fn ggx_g1(w: &Vec3, alpha: f64) -> f64 {
+    let cos2 = w.z() * w.z();
+    if cos2 <= 0.0 {
+        return 0.0;
+    }
+    let tan2 = (1.0 - cos2) / cos2;
+    2.0 / (1.0 + (1.0 + alpha * alpha * tan2).sqrt())
+}
+
+fn ggx_g(wo: &Vec3, wi: &Vec3, alpha: f64) -> f64 {
+    ggx_g1(wo, alpha) * ggx_g1(wi, alpha)
+}
+
+/**
+ * samples a microfacet normal proportional to D(h) * cos(h)
+ */
+fn ggx_sample_h(alpha: f64, u0: f64, u1: f64) -> Vec3 {
+    let tan2 = alpha * alpha * u0 / (1.0 - u0);
+    let cos_theta = 1.0 / (1.0 + tan2).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u1;
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+fn cosine_hemisphere(u0: f64, u1: f64) -> Vec3 {
+    let r = u0.sqrt();
+    let phi = 2.0 * PI * u1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u0).max(0.0).sqrt())
+}
+
+fn reflect(wo: &Vec3, n: &Vec3) -> Vec3 {
+    *n * (2.0 * Vec3::dot(wo, n)) - *wo
+}
+
+/**
+ * `eta` is the ior of the side we refract into over the side of `wo`, None on total internal reflection
+ */
+fn refract(wo: &Vec3, n: &Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = Vec3::dot(wo, n);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(*wo * (-1.0 / eta) + *n * (cos_i / eta - cos_t))
+}
+
+pub fn fresnel_schlick(f0: &Vec3, cos_theta: f64) -> Vec3 {
+    let t = (1.0 - cos_theta).clamp(0.0, 1.0).powi(5);
+    *f0 + (Vec3::WHITE - *f0) * t
+}
+
+/**
+ * unpolarized fresnel reflectance, `eta` is the ior of the transmitted side over the incident side
+ */
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    (rs * rs + rp * rp) / 2.0
+}
+
+#[test]
+fn test_bsdf_sample_matches_eval() {
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+    let bsdfs: Vec<(&str, Box<dyn Bsdf>)> = vec![
+        ("lambertian", Box::new(Lambertian { albedo: Vec3::WHITE })),
+        (
+            "conductor",
+            Box::new(Conductor {
+                f0: Vec3::WHITE,
+                roughness: 0.4,
+            }),
+        ),
+        (
+            "dielectric",
+            Box::new(Dielectric {
+                ior: 1.5,
+                roughness: 0.3,
+                tint: Vec3::WHITE,
+            }),
+        ),
+        (
+            "principled",
+            Box::new(Principled {
+                base_color: Vec3::new(0.8, 0.3, 0.2),
+                metallic: 0.3,
+                roughness: 0.5,
+                specular: 0.04,
+                transmission: 0.5,
+                ior: 1.45,
+            }),
+        ),
+    ];
+    for (name, bsdf) in bsdfs.iter() {
+        for front_face in [true, false].iter() {
+            let mut wo = Vec3::new(0.3, -0.2, 0.8);
+            wo.normalize();
+            let samples = 20000;
+            let mut albedo = 0.0;
+            for _ in 0..samples {
+                let u = (rng.gen(), rng.gen(), rng.gen());
+                if let Some(sample) = bsdf.sample(&wo, *front_face, u) {
+                    let value = bsdf.eval(&wo, &sample.wi, *front_face);
+                    let pdf = bsdf.pdf(&wo, &sample.wi, *front_face);
+                    assert!(
+                        (pdf - sample.pdf).abs() <= 1e-6 * pdf.max(1.0),
+                        "{} pdf {} but sampled with {}",
+                        name,
+                        pdf,
+                        sample.pdf
+                    );
+                    assert!(
+                        (value - sample.value).length() <= 1e-6 * value.length().max(1.0),
+                        "{} eval {:?} but sampled {:?}",
+                        name,
+                        value,
+                        sample.value
+                    );
+                    albedo += sample.weight().x() / samples as f64;
+                }
+            }
+            assert!(albedo <= 1.02, "{} reflects {} of the light", name, albedo);
+        }
+    }
+}
+
+#[test]
+fn test_smooth_dielectric() {
+    let glass = Dielectric {
+        ior: 1.5,
+        roughness: 0.0,
+        tint: Vec3::WHITE,
+    };
+    let wo = Vec3::new(0.0, 0.0, 1.0);
+    let reflected = glass.sample(&wo, true, (0.0, 0.5, 0.5)).unwrap();
+    assert!((reflected.pdf - 0.04).abs() < 1e-9);
+    assert!(reflected.specular && reflected.wi.z() > 0.0);
+
+    let refracted = glass.sample(&wo, true, (0.5, 0.5, 0.5)).unwrap();
+    assert!(refracted.specular && (refracted.wi.z() + 1.0).abs() < 1e-9);
+
+    // leaving the glass at a grazing angle is total internal reflection
+    let mut grazing = Vec3::new(0.9, 0.0, 0.2);
+    grazing.normalize();
+    let sample = glass.sample(&grazing, false, (0.99, 0.5, 0.5)).unwrap();
+    assert!(sample.wi.z() > 0.0, "should reflect, got {:?}", sample.wi);
+}
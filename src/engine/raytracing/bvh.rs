@@ -1,12 +1,397 @@
+use super::object::Object;
+use crate::Ray;
+use crate::Vec3;
+use std::marker::PhantomData;
 
+/**
+ * bounding volume hierarchy over a slice of objects.
+ * the tree only stores indices, the objects stay where they are.
+ */
+#[derive(Debug)]
 pub struct Bvh<T> {
-    left: Vec<Box<Bvh<T>>>,
-    right: Vec<Box<Bvh<T>>>,
-    object: Box<T>,
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+    object_type: PhantomData<T>,
+}
+
+#[derive(Debug)]
+struct BvhNode {
+    bounds: Aabb,
+    /**
+     * for a leaf this is the first item in `indices`, otherwise the left child. the right child is always `first + 1` for inner nodes
+     */
+    first: usize,
+    /**
+     * 0 means this is an inner node
+     */
+    count: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Aabb {
+    min: Vec3,
+    max: Vec3,
+}
+
+impl Aabb {
+    const EMPTY: Aabb = Aabb {
+        min: Vec3 {
+            value: [f64::INFINITY; 3],
+        },
+        max: Vec3 {
+            value: [f64::NEG_INFINITY; 3],
+        },
+    };
+    fn grow(&mut self, other: &Aabb) {
+        for i in 0..3 {
+            self.min.value[i] = self.min.value[i].min(other.min.value[i]);
+            self.max.value[i] = self.max.value[i].max(other.max.value[i]);
+        }
+    }
+    fn grow_point(&mut self, point: &Vec3) {
+        for i in 0..3 {
+            self.min.value[i] = self.min.value[i].min(point.value[i]);
+            self.max.value[i] = self.max.value[i].max(point.value[i]);
+        }
+    }
+    fn centroid(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+    fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        if d.x() < 0.0 || d.y() < 0.0 || d.z() < 0.0 {
+            return 0.0;
+        }
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+    /**
+     * slab test, returns the distance at which the ray enters the box
+     */
+    fn hit(&self, origin: &Vec3, inv_dir: &Vec3, t_max: f64) -> Option<f64> {
+        let mut t_enter = 0.0f64;
+        let mut t_exit = t_max;
+        for i in 0..3 {
+            let t0 = (self.min.value[i] - origin.value[i]) * inv_dir.value[i];
+            let t1 = (self.max.value[i] - origin.value[i]) * inv_dir.value[i];
+            let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            // NaN happens when the ray lies exactly on a slab plane, `max`/`min` skip it
+            t_enter = t_enter.max(t0);
+            t_exit = t_exit.min(t1);
+        }
+        if t_enter <= t_exit {
+            Some(t_enter)
+        } else {
+            None
+        }
+    }
+}
+
+struct BuildItem {
+    bounds: Aabb,
+    centroid: Vec3,
+    index: usize,
+}
+
+const BIN_COUNT: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+/**
+ * relative cost of one ray/box test compared to one ray/object test
+ */
+const TRAVERSAL_COST: f64 = 0.5;
+
+impl<T: Object> Bvh<T> {
+    pub fn new(objects: &[T]) -> Bvh<T> {
+        let mut items: Vec<BuildItem> = objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| {
+                let (min, max) = object.bounding_box();
+                let bounds = Aabb { min, max };
+                BuildItem {
+                    bounds,
+                    centroid: bounds.centroid(),
+                    index,
+                }
+            })
+            .collect();
+
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(objects.len() * 2),
+            indices: vec![],
+            object_type: PhantomData,
+        };
+        if !items.is_empty() {
+            bvh.nodes.push(BvhNode {
+                bounds: Aabb::EMPTY,
+                first: 0,
+                count: 0,
+            });
+            bvh.build(0, &mut items, 0);
+        }
+        bvh.indices = items.into_iter().map(|item| item.index).collect();
+        bvh
+    }
+
+    /**
+     * `items` is the part of the item list owned by `node`, `offset` is where it starts in the whole list
+     */
+    fn build(&mut self, node: usize, items: &mut [BuildItem], offset: usize) {
+        let mut bounds = Aabb::EMPTY;
+        let mut centroid_bounds = Aabb::EMPTY;
+        for item in items.iter() {
+            bounds.grow(&item.bounds);
+            centroid_bounds.grow_point(&item.centroid);
+        }
+        self.nodes[node].bounds = bounds;
+        self.nodes[node].first = offset;
+        self.nodes[node].count = items.len();
+
+        if items.len() <= 1 {
+            return;
+        }
+
+        let split = Self::find_split(items, &bounds, &centroid_bounds);
+
+        let mid = match split {
+            Some((axis, position, cost)) => {
+                let leaf_cost = items.len() as f64;
+                if items.len() <= MAX_LEAF_SIZE && cost >= leaf_cost {
+                    return;
+                }
+                partition(items, |item| item.centroid.value[axis] < position)
+            }
+            None => {
+                // all centroids are at the same point
+                if items.len() <= MAX_LEAF_SIZE {
+                    return;
+                }
+                items.len() / 2
+            }
+        };
+        // a degenerated split would recurse forever
+        let mid = if mid == 0 || mid == items.len() {
+            items.len() / 2
+        } else {
+            mid
+        };
+
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode {
+            bounds: Aabb::EMPTY,
+            first: 0,
+            count: 0,
+        });
+        self.nodes.push(BvhNode {
+            bounds: Aabb::EMPTY,
+            first: 0,
+            count: 0,
+        });
+        self.nodes[node].first = left;
+        self.nodes[node].count = 0;
+
+        let (left_items, right_items) = items.split_at_mut(mid);
+        self.build(left, left_items, offset);
+        self.build(left + 1, right_items, offset + mid);
+    }
+
+    /**
+     * binned surface area heuristic, returns (axis, split position, cost relative to one object test)
+     */
+    fn find_split(
+        items: &[BuildItem],
+        parent_bounds: &Aabb,
+        centroid_bounds: &Aabb,
+    ) -> Option<(usize, f64, f64)> {
+        let mut best: Option<(usize, f64, f64)> = None;
+        let parent_area = parent_bounds.surface_area();
+
+        for axis in 0..3 {
+            let axis_min = centroid_bounds.min.value[axis];
+            let axis_max = centroid_bounds.max.value[axis];
+            if axis_max - axis_min <= 0.0 {
+                continue;
+            }
+            let scale = BIN_COUNT as f64 / (axis_max - axis_min);
+
+            let mut bin_bounds = [Aabb::EMPTY; BIN_COUNT];
+            let mut bin_counts = [0usize; BIN_COUNT];
+            for item in items.iter() {
+                let bin = (((item.centroid.value[axis] - axis_min) * scale) as usize)
+                    .min(BIN_COUNT - 1);
+                bin_bounds[bin].grow(&item.bounds);
+                bin_counts[bin] += 1;
+            }
+
+            // sweep from the right to get the area and count of everything right of each plane
+            let mut right_area = [0.0; BIN_COUNT];
+            let mut right_count = [0usize; BIN_COUNT];
+            let mut acc_bounds = Aabb::EMPTY;
+            let mut acc_count = 0;
+            for bin in (1..BIN_COUNT).rev() {
+                acc_bounds.grow(&bin_bounds[bin]);
+                acc_count += bin_counts[bin];
+                right_area[bin] = acc_bounds.surface_area();
+                right_count[bin] = acc_count;
+            }
+
+            let mut acc_bounds = Aabb::EMPTY;
+            let mut acc_count = 0;
+            for bin in 1..BIN_COUNT {
+                acc_bounds.grow(&bin_bounds[bin - 1]);
+                acc_count += bin_counts[bin - 1];
+                if acc_count == 0 || right_count[bin] == 0 {
+                    continue;
+                }
+                let cost = if parent_area > 0.0 {
+                    TRAVERSAL_COST
+                        + (acc_bounds.surface_area() * acc_count as f64
+                            + right_area[bin] * right_count[bin] as f64)
+                            / parent_area
+                } else {
+                    TRAVERSAL_COST + items.len() as f64 / 2.0
+                };
+                if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, axis_min + bin as f64 / scale, cost));
+                }
+            }
+        }
+        best
+    }
 }
 
 impl<T> Bvh<T> {
-    pub fn new( ){
-        unimplemented!()
+    /**
+     * finds the nearest hit along the ray.
+     * `hit` tests one object, given its index and the current nearest distance so it can return None for anything farther.
+     * it returns the distance to the hit point along with whatever the caller needs.
+     */
+    pub fn intersect<'a, R>(
+        &self,
+        objects: &'a [T],
+        ray: &Ray,
+        mut hit: impl FnMut(usize, &'a T, f64) -> Option<(f64, R)>,
+    ) -> Option<R> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let origin = ray.origin.xyz();
+        let dir = ray.dir.xyz();
+        let inv_dir = Vec3::new(1.0 / dir.x(), 1.0 / dir.y(), 1.0 / dir.z());
+
+        let mut nearest = f64::INFINITY;
+        let mut res = None;
+
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if node.bounds.hit(&origin, &inv_dir, nearest).is_none() {
+                continue;
+            }
+            if node.count > 0 {
+                for index in &self.indices[node.first..node.first + node.count] {
+                    if let Some((distance, result)) = hit(*index, &objects[*index], nearest) {
+                        if distance < nearest {
+                            nearest = distance;
+                            res = Some(result);
+                        }
+                    }
+                }
+                continue;
+            }
+            let left = node.first;
+            let right = node.first + 1;
+            let left_hit = self.nodes[left].bounds.hit(&origin, &inv_dir, nearest);
+            let right_hit = self.nodes[right].bounds.hit(&origin, &inv_dir, nearest);
+            // push the farther child first so the nearer one gets visited first and shrinks `nearest`
+            match (left_hit, right_hit) {
+                (Some(l), Some(r)) => {
+                    if l < r {
+                        stack.push(right);
+                        stack.push(left);
+                    } else {
+                        stack.push(left);
+                        stack.push(right);
+                    }
+                }
+                (Some(_), None) => stack.push(left),
+                (None, Some(_)) => stack.push(right),
+                (None, None) => {}
+            }
+        }
+        res
+    }
+}
+
+fn partition<T>(items: &mut [T], predicate: impl Fn(&T) -> bool) -> usize {
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if predicate(&items[i]) {
+            items.swap(i, mid);
+            mid += 1;
+        }
     }
-}
\ No newline at end of file
+    mid
+}
+
+#[test]
+fn test_bvh_nearest_hit() {
+    use crate::{Material, Scene, Vec4};
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+    let mut scene = Scene::new();
+    for _ in 0..500 {
+        scene.add_sphere(
+            Vec4::new(
+                rng.gen::<f64>() * 200.0 - 100.0,
+                rng.gen::<f64>() * 200.0 - 100.0,
+                rng.gen::<f64>() * 200.0 - 100.0,
+                1.0,
+            ),
+            rng.gen::<f64>() * 5.0 + 0.5,
+            Material::RUBBER,
+            Vec3::WHITE,
+        );
+    }
+    let linear_scene = Scene {
+        objects: scene.objects.clone(),
+        bvh: None,
+        lights: vec![],
+    };
+    scene.build_bvh();
+
+    for _ in 0..500 {
+        let ray = Ray {
+            origin: Vec4::new(
+                rng.gen::<f64>() * 300.0 - 150.0,
+                rng.gen::<f64>() * 300.0 - 150.0,
+                rng.gen::<f64>() * 300.0 - 150.0,
+                1.0,
+            ),
+            dir: Vec4::new(
+                rng.gen::<f64>() * 2.0 - 1.0,
+                rng.gen::<f64>() * 2.0 - 1.0,
+                rng.gen::<f64>() * 2.0 - 1.0,
+                1.0,
+            )
+            .normalize(),
+        };
+        let expected = ray.intersect(&linear_scene).map(|hit| hit.point);
+        let actual = ray.intersect(&scene).map(|hit| hit.point);
+        match (expected, actual) {
+            (None, None) => {}
+            (Some(expected), Some(actual)) => assert!(
+                (expected - actual).length() < 1e-6,
+                "bvh hit {:?} but linear scan hit {:?}",
+                actual,
+                expected
+            ),
+            (expected, actual) => panic!(
+                "bvh hit {:?} but linear scan hit {:?}",
+                actual, expected
+            ),
+        }
+    }
+}
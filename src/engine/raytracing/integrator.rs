@@ -0,0 +1,391 @@
+use super::bsdf::{Bsdf, Onb};
+use crate::IntersectionResult;
+use crate::Ray;
+use crate::Scene;
+use crate::Vec3;
+use crate::Vec4;
+use super::sampler::SampleStream;
+
+/**
+ * how the radiance along a camera ray is estimated
+ */
+#[derive(Debug, Clone, Copy)]
+pub enum Integrator {
+    /**
+     * the original recursive tracer, a fixed fan-out of diffuse, reflected and refracted rays.
+     * biased, kept so old renders can be reproduced
+     */
+    Whitted,
+    /**
+     * unbiased path tracing with next event estimation and multiple importance sampling
+     */
+    PathTracer {
+        max_depth: usize,
+        /**
+         * paths longer than this get terminated randomly by their throughput
+         */
+        russian_roulette_depth: usize,
+    },
+}
+
+impl Default for Integrator {
+    fn default() -> Integrator {
+        Integrator::PathTracer {
+            max_depth: 16,
+            russian_roulette_depth: 3,
+        }
+    }
+}
+
+impl Integrator {
+    /**
+     * every random decision draws from `samples`, the same sample gives the same estimate
+     */
+    pub fn radiance(&self, ray: Ray, scene: &Scene, samples: &mut SampleStream) -> Vec3 {
+        match *self {
+            Integrator::Whitted => get_color(ray, scene, 1.0, false, true, samples),
+            Integrator::PathTracer {
+                max_depth,
+                russian_roulette_depth,
+            } => path_trace(ray, scene, max_depth, russian_roulette_depth, samples),
+        }
+    }
+}
+
+/**
+ * small offset along the normal so a new ray does not hit the surface it starts from
+ */
+const EPSILON: f64 = 0.002;
+
+fn path_trace(
+    mut ray: Ray,
+    scene: &Scene,
+    max_depth: usize,
+    russian_roulette_depth: usize,
+    samples: &mut SampleStream,
+) -> Vec3 {
+    let mut radiance = Vec3::BLACK;
+    let mut throughput = Vec3::WHITE;
+    // pdf of the bsdf sample that produced `ray`, None for camera rays and specular bounces
+    let mut bsdf_pdf: Option<f64> = None;
+    let mut last_point = ray.origin.xyz();
+
+    for depth in 0..max_depth {
+        let intersection = match ray.intersect(scene) {
+            Some(intersection) => intersection,
+            None => {
+                radiance = radiance + Vec3::multiply(&throughput, &sky_color(&ray));
+                break;
+            }
+        };
+        let IntersectionResult {
+            primitive,
+            primitive_index,
+            point,
+            normal,
+            front_face,
+            uv,
+            ..
+        } = intersection;
+        let material = primitive.material();
+        let n = normal.xyz();
+        let bsdf = material.bsdf_at(&uv);
+        // the normal map only bends the frame the bsdf is evaluated in, rays still leave by `n`
+        let shading_normal = if material.normal_map.is_some() {
+            material.shading_normal(&uv, &n, &primitive.tangents(&point.xyz()))
+        } else {
+            n
+        };
+
+        let emission = &material.emission;
+        if emission.x() > 0.0 || emission.y() > 0.0 || emission.z() > 0.0 {
+            let weight = match bsdf_pdf {
+                None => 1.0,
+                Some(bsdf_pdf) => {
+                    let light_pdf = scene.light_pdf(primitive_index, &last_point, &point.xyz(), &n);
+                    power_heuristic(bsdf_pdf, light_pdf)
+                }
+            };
+            radiance = radiance + Vec3::multiply(&throughput, emission) * weight;
+        }
+
+        let onb = Onb::from_normal(&shading_normal);
+        let wo = onb.to_local(&(ray.dir.xyz() * -1.0));
+        let color = primitive.color();
+
+        // next event estimation
+        for light in scene.lights.iter() {
+            let u = samples.get_3d();
+            let sample = match light.sample(&scene.objects, &point.xyz(), u) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            let wi = onb.to_local(&sample.direction.xyz());
+            let f = bsdf.eval(&wo, &wi, front_face);
+            if f.x() <= 0.0 && f.y() <= 0.0 && f.z() <= 0.0 {
+                continue;
+            }
+            // the shadow ray starts off the surface, aim it from there at the sampled light point
+            let origin = offset_point(&point, &n, Vec3::dot(&sample.direction.xyz(), &n));
+            let (shadow_ray, light_distance) = if sample.distance.is_finite() {
+                let light_point = point.xyz() + sample.direction.xyz() * sample.distance;
+                let mut dir = light_point - origin.xyz();
+                let light_distance = dir.length();
+                dir.normalize();
+                (Ray { origin, dir: Vec4::from(dir) }, light_distance)
+            } else {
+                (Ray { origin, dir: sample.direction }, sample.distance)
+            };
+            if shadow_ray.occluded(scene, light_distance * (1.0 - 1e-4)) {
+                continue;
+            }
+            let weight = if light.is_delta() {
+                1.0
+            } else {
+                power_heuristic(sample.pdf, bsdf.pdf(&wo, &wi, front_face))
+            };
+            let contribution = Vec3::multiply(&Vec3::multiply(&f, &sample.radiance), &color)
+                * (wi.z().abs() * weight);
+            radiance = radiance + Vec3::multiply(&throughput, &contribution);
+        }
+
+        let u = samples.get_3d();
+        let sample = match bsdf.sample(&wo, front_face, u) {
+            Some(sample) => sample,
+            None => break,
+        };
+        throughput = Vec3::multiply(&Vec3::multiply(&throughput, &sample.weight()), &color);
+        bsdf_pdf = if sample.specular {
+            None
+        } else {
+            Some(sample.pdf)
+        };
+
+        let max_throughput = throughput.x().max(throughput.y()).max(throughput.z());
+        if max_throughput <= 0.0 {
+            break;
+        }
+        if depth + 1 >= russian_roulette_depth {
+            let survive = max_throughput.min(0.95);
+            if samples.get_1d() >= survive {
+                break;
+            }
+            throughput = throughput / survive;
+        }
+
+        let wi = onb.to_world(&sample.wi);
+        last_point = point.xyz();
+        ray = Ray {
+            origin: offset_point(&point, &n, Vec3::dot(&wi, &n)),
+            dir: Vec4::from(wi).normalize(),
+        };
+    }
+    radiance
+}
+
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b <= 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
+/**
+ * moves the point off the surface, to the side the new ray leaves through
+ */
+fn offset_point(point: &Vec4, normal: &Vec3, cos_theta: f64) -> Vec4 {
+    let offset = if cos_theta < 0.0 { -EPSILON } else { EPSILON };
+    Vec4::from(point.xyz() + *normal * offset)
+}
+
+fn sky_color(ray: &Ray) -> Vec3 {
+    // 天空颜色
+    let sky_color_bottom: Vec3 = Vec3::new(0.9, 0.9, 0.9);
+    let sky_color_top: Vec3 = Vec3::new(0.5, 0.7, 0.9);
+    let t = 0.5 * (ray.dir.y() + 1.0);
+    sky_color_bottom * (1.0 - t) + sky_color_top * t
+}
+
+/**
+ * `count_emission` is false after a diffuse bounce, the light sources were already sampled directly there
+ */
+fn get_color(
+    ray: Ray,
+    scene: &Scene,
+    intensity: f64,
+    simple_mode: bool,
+    count_emission: bool,
+    samples: &mut SampleStream,
+) -> Vec3 {
+    if intensity < 0.005 {
+        return Vec3::BLACK;
+    }
+    if let Some(intersection) = ray.intersect(scene) {
+        let IntersectionResult {
+            primitive,
+            point,
+            normal,
+            refraction_ratio,
+            uv,
+            ..
+        } = intersection;
+        let material = primitive.material();
+        let normal = if material.normal_map.is_some() {
+            let tangents = primitive.tangents(&point.xyz());
+            Vec4::from(material.shading_normal(&uv, &normal.xyz(), &tangents))
+        } else {
+            normal
+        };
+        let reflectance = material.reflectance();
+        let refraction = material.refraction();
+        let reflect_fuzziness = material.reflect_fuzziness();
+        let diffuse = material.diffuse();
+        //这个0.5 表示我们的材料吸收一半光照
+        let mut cur_color = Vec3::ORIGIN;
+        if diffuse > 0.0 {
+            let num_of_diffuse_rays;
+            if simple_mode {
+                num_of_diffuse_rays = 1
+            } else {
+                num_of_diffuse_rays = 3
+            }
+            let direct_light = scene.direct_light(&(point + normal * 0.002), &normal, samples) / std::f64::consts::PI;
+            cur_color = cur_color + direct_light * (diffuse * intensity);
+            for _i in 0..num_of_diffuse_rays {
+                let epsilon = 0.002;
+                let diffuse_dir = (normal + noise_3d(0.6, samples)).normalize();
+                let diffuse_ray = Ray {
+                    origin: point + diffuse_dir * epsilon,
+                    dir: diffuse_dir,
+                };
+                cur_color = cur_color + get_color(diffuse_ray, scene, diffuse * intensity, true, false, samples) / num_of_diffuse_rays as f64;
+            }
+        }
+        if reflectance > 0.0 {
+            let epsilon = 0.002;
+            let mut refl_dir = (ray.dir - normal * (ray.dir * normal) * 2.0).normalize();
+            refl_dir += noise_3d(reflect_fuzziness, samples);
+            if refl_dir * normal < 0.0 {
+                //如果小于0 表示反射光线被反射到法线的相反方向了.
+                refl_dir = refl_dir - (refl_dir - normal) * 0.5;
+            }
+            let reflect_ray = Ray {
+                origin: point + refl_dir * epsilon,
+                dir: refl_dir.normalize(),
+            };
+            cur_color = cur_color + get_color(reflect_ray, scene, reflectance * intensity, true, true, samples);
+        }
+        if refraction > 0.0 {
+            let epsilon = 0.002;
+            let dt = ray.dir * normal;
+            let discreminant = 1.0 - refraction_ratio * refraction_ratio * (1.0 - dt * dt);
+            if discreminant > 0.0 {
+                let refr_dir =
+                    (ray.dir - normal * dt) * refraction_ratio - normal * discreminant.sqrt();
+                let refract_ray = Ray {
+                    origin: point + refr_dir * epsilon,
+                    dir: refr_dir,
+                };
+                // 折射系数不能超过0.8
+                let refraction_factor = f64::min(1.0 - reflectance - diffuse, 0.8);
+                // println!(">{:?}\n>>{:?}\n>>>{:?}", refract_ray, ray, primitive);
+                cur_color = cur_color
+                    + get_color(
+                        refract_ray,
+                        scene,
+                        refraction_factor * intensity,
+                        true,
+                        true,
+                        samples,
+                    );
+            }
+        }
+        let color = Vec3::multiply(&primitive.color(), &material.albedo_at(&uv));
+        let res = Vec3::new(
+            color.value[0] * cur_color.value[0],
+            color.value[1] * cur_color.value[1],
+            color.value[2] * cur_color.value[2],
+        ) * intensity;
+        if count_emission {
+            return res + material.emission * intensity;
+        }
+        return res;
+        // return cur_color * intensity;
+    } else {
+        return sky_color(&ray);
+    }
+}
+
+/**
+ * uniform point in a ball of `radius`, without rejection so every call takes three dimensions
+ */
+fn noise_3d(radius: f64, samples: &mut SampleStream) -> Vec4 {
+    let (u0, u1) = samples.get_2d();
+    let z = 1.0 - 2.0 * u0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * u1;
+    let length = samples.get_1d().cbrt() * radius;
+    Vec4::new(r * phi.cos() * length, r * phi.sin() * length, z * length, 1.0)
+}
+
+#[test]
+fn test_path_tracer_direct_lighting() {
+    use crate::{BsdfModel, Lambertian, Material, Mesh};
+    use crate::IndependentSampler;
+
+    let mut scene = Scene::new();
+    // a black box around everything so the sky does not light the scene
+    scene.add_sphere(
+        Vec4::new(0.0, 0.0, 0.0, 1.0),
+        1000.0,
+        Material {
+            bsdf: BsdfModel::Lambertian(Lambertian { albedo: Vec3::BLACK }),
+            emission: Vec3::BLACK,
+            albedo_map: None,
+            roughness_map: None,
+            normal_map: None,
+            whitted: None,
+        },
+        Vec3::WHITE,
+    );
+    scene.add_mesh(Mesh {
+        positions: vec![
+            Vec3::new(-100.0, 0.0, -100.0),
+            Vec3::new(-100.0, 0.0, 100.0),
+            Vec3::new(100.0, 0.0, 100.0),
+            Vec3::new(100.0, 0.0, -100.0),
+        ],
+        normals: vec![],
+        uvs: vec![],
+        indices: vec![[0, 1, 2], [0, 2, 3]],
+        material: Material::RUBBER,
+        color: Vec3::WHITE,
+    });
+    scene.add_sphere_light(Vec4::new(0.0, 10.0, 0.0, 1.0), 2.0, Vec3::WHITE);
+    scene.build_bvh();
+
+    // irradiance from the sphere light is pi * (r / d)^2, the plane reflects albedo / pi of it
+    let expected = 0.5 * (2.0f64 / 10.0).powi(2);
+    let integrator = Integrator::default();
+    let samples = 20000;
+    let sampler = IndependentSampler {
+        samples_per_pixel: samples,
+        seed: 11,
+    };
+    let mut sum = 0.0;
+    for index in 0..samples {
+        let ray = Ray {
+            origin: Vec4::new(0.0, 1.0, 1.0, 1.0),
+            dir: Vec4::new(0.0, -1.0, -1.0, 1.0).normalize(),
+        };
+        sum += integrator.radiance(ray, &scene, &mut SampleStream::new(&sampler, (0, 0), index)).x();
+    }
+    let estimate = sum / samples as f64;
+    assert!(
+        (estimate - expected).abs() / expected < 0.03,
+        "expected {}, got {}",
+        expected,
+        estimate
+    );
+}
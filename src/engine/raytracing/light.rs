@@ -0,0 +1,382 @@
+use crate::BsdfModel;
+use crate::Lambertian;
+use crate::Material;
+use crate::Mesh;
+use crate::Primitive;
+use crate::Ray;
+use crate::Scene;
+use crate::Vec3;
+use crate::Vec4;
+use crate::SampleStream;
+
+#[derive(Debug, Clone)]
+pub enum Light {
+    Point {
+        position: Vec3,
+        /**
+         * radiant intensity, falls off with the squared distance
+         */
+        intensity: Vec3,
+    },
+    Directional {
+        /**
+         * the direction light travels in
+         */
+        direction: Vec3,
+        radiance: Vec3,
+    },
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        /**
+         * cosine of the angle where the falloff starts
+         */
+        cos_inner: f64,
+        /**
+         * cosine of the angle where the light is cut off
+         */
+        cos_outer: f64,
+    },
+    /**
+     * emissive primitives of the scene, sampled proportional to their area.
+     * the emitted radiance comes from the primitive's material.
+     */
+    Area {
+        primitives: Vec<usize>,
+        /**
+         * running sum of the primitive areas
+         */
+        cdf: Vec<f64>,
+    },
+}
+
+#[derive(Debug)]
+pub struct LightSample {
+    /**
+     * unit vector from the shaded point towards the light
+     */
+    pub direction: Vec4,
+    pub distance: f64,
+    /**
+     * incoming radiance divided by the pdf of picking this direction
+     */
+    pub radiance: Vec3,
+    /**
+     * solid angle pdf of picking this direction, 1 for lights that can only be sampled, see `Light::is_delta`
+     */
+    pub pdf: f64,
+}
+
+impl Light {
+    /**
+     * angles are measured from the spot direction, in radians
+     */
+    pub fn spot(
+        position: Vec3,
+        mut direction: Vec3,
+        intensity: Vec3,
+        inner_angle: f64,
+        outer_angle: f64,
+    ) -> Light {
+        direction.normalize();
+        Light::Spot {
+            position,
+            direction,
+            intensity,
+            cos_inner: inner_angle.cos(),
+            cos_outer: outer_angle.cos(),
+        }
+    }
+    pub fn area(objects: &[Primitive], mut primitives: Vec<usize>) -> Light {
+        // kept sorted so `Scene::light_pdf` can binary search it
+        primitives.sort_unstable();
+        let mut total = 0.0;
+        let cdf = primitives
+            .iter()
+            .map(|index| {
+                total += primitive_area(&objects[*index]);
+                total
+            })
+            .collect();
+        Light::Area { primitives, cdf }
+    }
+    /**
+     * point, directional and spot lights cannot be hit by a ray, only sampled
+     */
+    pub fn is_delta(&self) -> bool {
+        !matches!(self, Light::Area { .. })
+    }
+    /**
+     * `u` are three uniform random numbers in [0, 1), only area lights use them
+     */
+    pub fn sample(
+        &self,
+        objects: &[Primitive],
+        point: &Vec3,
+        u: (f64, f64, f64),
+    ) -> Option<LightSample> {
+        match self {
+            Light::Point {
+                position,
+                intensity,
+            } => {
+                let (direction, distance) = direction_to(point, position)?;
+                Some(LightSample {
+                    direction,
+                    distance,
+                    radiance: *intensity / (distance * distance),
+                    pdf: 1.0,
+                })
+            }
+            Light::Directional {
+                direction,
+                radiance,
+            } => {
+                let mut to_light = *direction * -1.0;
+                to_light.normalize();
+                Some(LightSample {
+                    direction: Vec4::new(to_light.x(), to_light.y(), to_light.z(), 1.0),
+                    distance: f64::INFINITY,
+                    radiance: *radiance,
+                    pdf: 1.0,
+                })
+            }
+            Light::Spot {
+                position,
+                direction,
+                intensity,
+                cos_inner,
+                cos_outer,
+            } => {
+                let (to_light, distance) = direction_to(point, position)?;
+                let cos_theta = -Vec3::dot(&to_light.xyz(), direction);
+                if cos_theta <= *cos_outer {
+                    return None;
+                }
+                let falloff = if cos_theta >= *cos_inner {
+                    1.0
+                } else {
+                    let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+                    t * t * (3.0 - 2.0 * t)
+                };
+                Some(LightSample {
+                    direction: to_light,
+                    distance,
+                    radiance: *intensity * (falloff / (distance * distance)),
+                    pdf: 1.0,
+                })
+            }
+            Light::Area { primitives, cdf } => {
+                let total_area = *cdf.last()?;
+                if total_area <= 0.0 {
+                    return None;
+                }
+                let target = u.0 * total_area;
+                let picked = cdf.partition_point(|area| *area <= target).min(cdf.len() - 1);
+                let primitive = &objects[primitives[picked]];
+                let (light_point, light_normal) = sample_primitive(primitive, (u.1, u.2));
+                let (to_light, distance) = direction_to(point, &light_point)?;
+                // emission is two sided
+                let cos_light = Vec3::dot(&to_light.xyz(), &light_normal).abs();
+                if cos_light <= 0.0 {
+                    return None;
+                }
+                // area pdf converted to solid angle is d^2 / (cos * total area)
+                let pdf = distance * distance / (cos_light * total_area);
+                Some(LightSample {
+                    direction: to_light,
+                    distance,
+                    radiance: primitive.material().emission / pdf,
+                    pdf,
+                })
+            }
+        }
+    }
+}
+
+impl Scene {
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+    pub fn add_sphere_light(&mut self, origin: Vec4, radius: f64, radiance: Vec3) {
+        self.add_sphere(origin, radius, emissive_material(radiance), Vec3::BLACK);
+    }
+    /**
+     * a parallelogram spanned by `edge_u` and `edge_v` from `corner`
+     */
+    pub fn add_quad_light(&mut self, corner: Vec3, edge_u: Vec3, edge_v: Vec3, radiance: Vec3) {
+        self.add_mesh(Mesh {
+            positions: vec![corner, corner + edge_u, corner + edge_u + edge_v, corner + edge_v],
+            normals: vec![],
+            uvs: vec![],
+            indices: vec![[0, 1, 2], [0, 2, 3]],
+            material: emissive_material(radiance),
+            color: Vec3::BLACK,
+        });
+    }
+    /**
+     * solid angle pdf of `Light::sample` picking `light_point` on the primitive as seen from `point`,
+     * 0 when the primitive does not belong to an area light
+     */
+    pub fn light_pdf(
+        &self,
+        primitive_index: usize,
+        point: &Vec3,
+        light_point: &Vec3,
+        light_normal: &Vec3,
+    ) -> f64 {
+        for light in self.lights.iter() {
+            if let Light::Area { primitives, cdf } = light {
+                if primitives.binary_search(&primitive_index).is_err() {
+                    continue;
+                }
+                let total_area = cdf.last().copied().unwrap_or(0.0);
+                let (to_light, distance) = match direction_to(point, light_point) {
+                    Some(res) => res,
+                    None => return 0.0,
+                };
+                let cos_light = Vec3::dot(&to_light.xyz(), light_normal).abs();
+                if cos_light <= 0.0 || total_area <= 0.0 {
+                    return 0.0;
+                }
+                return distance * distance / (cos_light * total_area);
+            }
+        }
+        0.0
+    }
+    /**
+     * samples every light once and sums up the unoccluded radiance arriving at `point`,
+     * each sample weighted by the cosine to `normal`
+     */
+    pub fn direct_light(&self, point: &Vec4, normal: &Vec4, samples: &mut SampleStream) -> Vec3 {
+        let position = point.xyz();
+        let n = normal.xyz();
+        let mut res = Vec3::BLACK;
+        for light in self.lights.iter() {
+            let u = samples.get_3d();
+            if let Some(sample) = light.sample(&self.objects, &position, u) {
+                let cos_theta = Vec3::dot(&sample.direction.xyz(), &n);
+                if cos_theta <= 0.0 {
+                    continue;
+                }
+                let shadow_ray = Ray {
+                    origin: *point,
+                    dir: sample.direction,
+                };
+                // stop a little before the light so we do not hit the light itself
+                if shadow_ray.occluded(self, sample.distance * (1.0 - 1e-4)) {
+                    continue;
+                }
+                res = res + sample.radiance * cos_theta;
+            }
+        }
+        res
+    }
+}
+
+fn emissive_material(radiance: Vec3) -> Material {
+    Material {
+        bsdf: BsdfModel::Lambertian(Lambertian {
+            albedo: Vec3::BLACK,
+        }),
+        emission: radiance,
+        albedo_map: None,
+        roughness_map: None,
+        normal_map: None,
+        whitted: None,
+    }
+}
+
+fn direction_to(from: &Vec3, to: &Vec3) -> Option<(Vec4, f64)> {
+    let mut d = *to - *from;
+    let distance = d.length();
+    if distance <= 0.0 {
+        return None;
+    }
+    d = d / distance;
+    Some((Vec4::new(d.x(), d.y(), d.z(), 1.0), distance))
+}
+
+fn primitive_area(primitive: &Primitive) -> f64 {
+    match primitive {
+        Primitive::Sphere(sphere) => 4.0 * std::f64::consts::PI * sphere.radius * sphere.radius,
+        Primitive::Triangle(triangle) => {
+            let [p0, p1, p2] = triangle.vertices();
+            Vec3::cross(&(p1 - p0), &(p2 - p0)).length() / 2.0
+        }
+    }
+}
+
+/**
+ * uniform point on the surface, returns (point, normal)
+ */
+fn sample_primitive(primitive: &Primitive, u: (f64, f64)) -> (Vec3, Vec3) {
+    match primitive {
+        Primitive::Sphere(sphere) => {
+            let z = 1.0 - 2.0 * u.0;
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let phi = 2.0 * std::f64::consts::PI * u.1;
+            let normal = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+            (sphere.origin.xyz() + normal * sphere.radius, normal)
+        }
+        Primitive::Triangle(triangle) => {
+            let [p0, p1, p2] = triangle.vertices();
+            let su = u.0.sqrt();
+            let b0 = 1.0 - su;
+            let b1 = u.1 * su;
+            let point = p0 * b0 + p1 * b1 + p2 * (1.0 - b0 - b1);
+            let mut normal = Vec3::cross(&(p1 - p0), &(p2 - p0));
+            normal.normalize();
+            (point, normal)
+        }
+    }
+}
+
+#[test]
+fn test_direct_light() {
+    use crate::IndependentSampler;
+
+    let sampler = IndependentSampler {
+        samples_per_pixel: 20000,
+        seed: 3,
+    };
+    let mut stream = SampleStream::new(&sampler, (0, 0), 0);
+    let point = Vec4::new(0.0, 0.0, 0.0, 1.0);
+    let normal = Vec4::new(0.0, 1.0, 0.0, 1.0);
+    {
+        let mut scene = Scene::new();
+        scene.add_light(Light::Point {
+            position: Vec3::new(0.0, 10.0, 0.0),
+            intensity: Vec3::new(100.0, 100.0, 100.0),
+        });
+        scene.build_bvh();
+        let light = scene.direct_light(&point, &normal, &mut stream);
+        assert!((light.x() - 1.0).abs() < 1e-9, "expected 1.0, got {:?}", light);
+
+        scene.add_sphere(Vec4::new(0.0, 5.0, 0.0, 1.0), 1.0, Material::RUBBER, Vec3::WHITE);
+        scene.build_bvh();
+        let light = scene.direct_light(&point, &normal, &mut stream);
+        assert_eq!(light.x(), 0.0, "the sphere should cast a shadow");
+    }
+    {
+        // a sphere light of radius r at distance d gives an irradiance of pi * L * (r / d)^2
+        let mut scene = Scene::new();
+        scene.add_sphere_light(Vec4::new(0.0, 10.0, 0.0, 1.0), 2.0, Vec3::WHITE);
+        scene.build_bvh();
+        let samples = 20000;
+        let mut sum = 0.0;
+        for index in 0..samples {
+            let mut stream = SampleStream::new(&sampler, (0, 0), index);
+            sum += scene.direct_light(&point, &normal, &mut stream).x();
+        }
+        let expected = std::f64::consts::PI * (2.0f64 / 10.0).powi(2);
+        let estimate = sum / samples as f64;
+        assert!(
+            (estimate - expected).abs() / expected < 0.05,
+            "expected {}, got {}",
+            expected,
+            estimate
+        );
+    }
+}
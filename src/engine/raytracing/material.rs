@@ -1,41 +1,329 @@
+use super::bsdf::*;
+use crate::Texture;
+use crate::Vec2;
+use crate::Vec3;
+use std::sync::Arc;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Material {
+    pub bsdf: BsdfModel,
+    /**
+     * radiance emitted by the surface itself
+     */
+    pub emission: Vec3,
+    /**
+     * multiplies the albedo, base color, f0 or tint of the bsdf
+     */
+    pub albedo_map: Option<Arc<Texture>>,
+    /**
+     * the green channel multiplies the roughness, like in glTF
+     */
+    pub roughness_map: Option<Arc<Texture>>,
+    /**
+     * tangent space normals, green points along +v
+     */
+    pub normal_map: Option<Arc<Texture>>,
+    /**
+     * fixed weights for the recursive tracer, None derives them from the bsdf.
+     * a reference keeps materials small, they are stored in every sphere
+     */
+    pub whitted: Option<&'static WhittedWeights>,
+}
+
+/**
+ * how the recursive tracer mixes a diffuse, a reflected and a refracted ray.
+ * the presets keep the weights they had before materials had a bsdf, so old renders still match
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct WhittedWeights {
     pub diffuse: f64,
     pub reflectance: f64,
     pub refraction: f64,
     pub reflect_fuzziness: f64,
 }
 
+/**
+ * the bsdfs a material can use. an enum rather than a boxed trait object so materials stay `const`
+ */
+#[derive(Debug, Clone)]
+pub enum BsdfModel {
+    Lambertian(Lambertian),
+    Conductor(Conductor),
+    Dielectric(Dielectric),
+    Principled(Principled),
+}
+
+impl Bsdf for BsdfModel {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, front_face: bool) -> Vec3 {
+        match self {
+            BsdfModel::Lambertian(bsdf) => bsdf.eval(wo, wi, front_face),
+            BsdfModel::Conductor(bsdf) => bsdf.eval(wo, wi, front_face),
+            BsdfModel::Dielectric(bsdf) => bsdf.eval(wo, wi, front_face),
+            BsdfModel::Principled(bsdf) => bsdf.eval(wo, wi, front_face),
+        }
+    }
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, front_face: bool) -> f64 {
+        match self {
+            BsdfModel::Lambertian(bsdf) => bsdf.pdf(wo, wi, front_face),
+            BsdfModel::Conductor(bsdf) => bsdf.pdf(wo, wi, front_face),
+            BsdfModel::Dielectric(bsdf) => bsdf.pdf(wo, wi, front_face),
+            BsdfModel::Principled(bsdf) => bsdf.pdf(wo, wi, front_face),
+        }
+    }
+    fn sample(&self, wo: &Vec3, front_face: bool, u: (f64, f64, f64)) -> Option<BsdfSample> {
+        match self {
+            BsdfModel::Lambertian(bsdf) => bsdf.sample(wo, front_face, u),
+            BsdfModel::Conductor(bsdf) => bsdf.sample(wo, front_face, u),
+            BsdfModel::Dielectric(bsdf) => bsdf.sample(wo, front_face, u),
+            BsdfModel::Principled(bsdf) => bsdf.sample(wo, front_face, u),
+        }
+    }
+}
+
 impl Material {
     pub const METAL: Material = Material {
-        diffuse: 0.0,
-        reflectance: 0.5,
-        refraction: 0.0,
-        reflect_fuzziness: 0.10,
+        bsdf: BsdfModel::Conductor(Conductor {
+            f0: Vec3::new(0.5, 0.5, 0.5),
+            roughness: 0.10,
+        }),
+        emission: Vec3::BLACK,
+        albedo_map: None,
+        roughness_map: None,
+        normal_map: None,
+        whitted: Some(&WhittedWeights {
+            diffuse: 0.0,
+            reflectance: 0.5,
+            refraction: 0.0,
+            reflect_fuzziness: 0.10,
+        }),
     };
     pub const MIRROR: Material = Material {
-        diffuse: 0.0,
-        reflectance: 0.5,
-        refraction: 0.0,
-        reflect_fuzziness: 0.0,
+        bsdf: BsdfModel::Conductor(Conductor {
+            f0: Vec3::new(0.5, 0.5, 0.5),
+            roughness: 0.0,
+        }),
+        emission: Vec3::BLACK,
+        albedo_map: None,
+        roughness_map: None,
+        normal_map: None,
+        whitted: Some(&WhittedWeights {
+            diffuse: 0.0,
+            reflectance: 0.5,
+            refraction: 0.0,
+            reflect_fuzziness: 0.0,
+        }),
     };
     pub const RUBBER: Material = Material {
-        diffuse: 0.5,
-        reflectance: 0.0,
-        refraction: 0.0,
-        reflect_fuzziness: 0.0,
+        bsdf: BsdfModel::Lambertian(Lambertian {
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+        }),
+        emission: Vec3::BLACK,
+        albedo_map: None,
+        roughness_map: None,
+        normal_map: None,
+        whitted: Some(&WhittedWeights {
+            diffuse: 0.5,
+            reflectance: 0.0,
+            refraction: 0.0,
+            reflect_fuzziness: 0.0,
+        }),
     };
     pub const GLASS: Material = Material {
-        diffuse: 0.0,
-        reflectance: 0.05,
-        refraction: 1.6,
-        reflect_fuzziness: 0.0,
+        bsdf: BsdfModel::Dielectric(Dielectric {
+            ior: 1.6,
+            roughness: 0.0,
+            tint: Vec3::WHITE,
+        }),
+        emission: Vec3::BLACK,
+        albedo_map: None,
+        roughness_map: None,
+        normal_map: None,
+        whitted: Some(&WhittedWeights {
+            diffuse: 0.0,
+            reflectance: 0.05,
+            refraction: 1.6,
+            reflect_fuzziness: 0.0,
+        }),
     };
     pub const WATER: Material = Material {
-        diffuse: 0.0,
-        reflectance: 0.2,
-        refraction: 1.33,
-        reflect_fuzziness: 0.0,
+        bsdf: BsdfModel::Dielectric(Dielectric {
+            ior: 1.33,
+            roughness: 0.0,
+            tint: Vec3::WHITE,
+        }),
+        emission: Vec3::BLACK,
+        albedo_map: None,
+        roughness_map: None,
+        normal_map: None,
+        whitted: Some(&WhittedWeights {
+            diffuse: 0.0,
+            reflectance: 0.2,
+            refraction: 1.33,
+            reflect_fuzziness: 0.0,
+        }),
+    };
+
+    /**
+     * the color of the albedo map at `uv`, white without one
+     */
+    pub fn albedo_at(&self, uv: &Vec2) -> Vec3 {
+        match &self.albedo_map {
+            Some(texture) => {
+                let texel = texture.sample(uv.x(), uv.y());
+                Vec3::new(texel.x(), texel.y(), texel.z())
+            }
+            None => Vec3::WHITE,
+        }
+    }
+    /**
+     * the bsdf with the albedo and roughness maps applied at `uv`
+     */
+    pub fn bsdf_at(&self, uv: &Vec2) -> BsdfModel {
+        let mut bsdf = self.bsdf.clone();
+        if self.albedo_map.is_some() {
+            let albedo = self.albedo_at(uv);
+            match &mut bsdf {
+                BsdfModel::Lambertian(bsdf) => bsdf.albedo = Vec3::multiply(&bsdf.albedo, &albedo),
+                BsdfModel::Conductor(bsdf) => bsdf.f0 = Vec3::multiply(&bsdf.f0, &albedo),
+                BsdfModel::Dielectric(bsdf) => bsdf.tint = Vec3::multiply(&bsdf.tint, &albedo),
+                BsdfModel::Principled(bsdf) => {
+                    bsdf.base_color = Vec3::multiply(&bsdf.base_color, &albedo)
+                }
+            }
+        }
+        if let Some(texture) = &self.roughness_map {
+            let roughness = texture.sample(uv.x(), uv.y()).y();
+            match &mut bsdf {
+                BsdfModel::Lambertian(_) => {}
+                BsdfModel::Conductor(bsdf) => bsdf.roughness *= roughness,
+                BsdfModel::Dielectric(bsdf) => bsdf.roughness *= roughness,
+                BsdfModel::Principled(bsdf) => bsdf.roughness *= roughness,
+            }
+        }
+        bsdf
+    }
+    /**
+     * `normal` bent by the normal map at `uv`. `tangents` are the surface directions of
+     * increasing u and v, `normal` may face either side
+     */
+    pub fn shading_normal(&self, uv: &Vec2, normal: &Vec3, tangents: &(Vec3, Vec3)) -> Vec3 {
+        let texture = match &self.normal_map {
+            Some(texture) => texture,
+            None => return *normal,
+        };
+        let texel = texture.sample(uv.x(), uv.y());
+        let (x, y, z) = (texel.x() * 2.0 - 1.0, texel.y() * 2.0 - 1.0, texel.z() * 2.0 - 1.0);
+        // gram-schmidt, the tangents of a mesh are not perpendicular to interpolated normals
+        let mut tangent = tangents.0 - *normal * Vec3::dot(&tangents.0, normal);
+        tangent.normalize();
+        let mut bitangent = Vec3::cross(normal, &tangent);
+        if Vec3::dot(&bitangent, &tangents.1) < 0.0 {
+            bitangent = bitangent * -1.0;
+        }
+        let mut res = tangent * x + bitangent * y + *normal * z;
+        res.normalize();
+        if Vec3::dot(&res, normal) <= 0.0 || res.x().is_nan() {
+            return *normal;
+        }
+        res
+    }
+
+    /**
+     * the weights of the recursive tracer in raytrace_pipeline, see `WhittedWeights`.
+     * they come from `whitted` when it is set and from the bsdf otherwise
+     */
+    pub fn diffuse(&self) -> f64 {
+        if let Some(weights) = self.whitted {
+            return weights.diffuse;
+        }
+        match &self.bsdf {
+            BsdfModel::Lambertian(bsdf) => average(&bsdf.albedo),
+            BsdfModel::Principled(bsdf) => {
+                average(&bsdf.base_color) * (1.0 - bsdf.metallic) * (1.0 - bsdf.transmission)
+            }
+            _ => 0.0,
+        }
+    }
+    pub fn reflectance(&self) -> f64 {
+        if let Some(weights) = self.whitted {
+            return weights.reflectance;
+        }
+        match &self.bsdf {
+            BsdfModel::Lambertian(_) => 0.0,
+            BsdfModel::Conductor(bsdf) => average(&bsdf.f0),
+            BsdfModel::Dielectric(bsdf) => fresnel_dielectric(1.0, bsdf.ior),
+            BsdfModel::Principled(bsdf) => {
+                bsdf.specular * (1.0 - bsdf.metallic) + average(&bsdf.base_color) * bsdf.metallic
+            }
+        }
+    }
+    /**
+     * index of refraction, 0 for opaque materials
+     */
+    pub fn refraction(&self) -> f64 {
+        if let Some(weights) = self.whitted {
+            return weights.refraction;
+        }
+        match &self.bsdf {
+            BsdfModel::Dielectric(bsdf) => bsdf.ior,
+            BsdfModel::Principled(bsdf) if bsdf.transmission > 0.0 => bsdf.ior,
+            _ => 0.0,
+        }
+    }
+    pub fn reflect_fuzziness(&self) -> f64 {
+        if let Some(weights) = self.whitted {
+            return weights.reflect_fuzziness;
+        }
+        match &self.bsdf {
+            BsdfModel::Lambertian(_) => 0.0,
+            BsdfModel::Conductor(bsdf) => bsdf.roughness,
+            BsdfModel::Dielectric(bsdf) => bsdf.roughness,
+            BsdfModel::Principled(bsdf) => bsdf.roughness,
+        }
+    }
+}
+
+fn average(color: &Vec3) -> f64 {
+    (color.x() + color.y() + color.z()) / 3.0
+}
+
+#[test]
+fn test_texture_maps() {
+    let texture = |texel: [f64; 4]| Some(Arc::new(Texture::new(1, 1, vec![texel])));
+    let material = Material {
+        albedo_map: texture([0.5, 1.0, 0.0, 1.0]),
+        roughness_map: texture([0.0, 0.5, 0.0, 1.0]),
+        normal_map: texture([0.5, 0.5, 1.0, 1.0]),
+        ..Material::METAL
     };
-}
\ No newline at end of file
+    let uv = Vec2::new(0.3, 0.7);
+    match material.bsdf_at(&uv) {
+        BsdfModel::Conductor(bsdf) => {
+            assert_eq!(bsdf.f0.z(), 0.0);
+            assert!((bsdf.roughness - 0.05).abs() < 1e-9);
+        }
+        _ => panic!("the maps changed the bsdf model"),
+    }
+
+    // a flat normal map keeps the geometric normal, on both sides
+    let tangents = (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+    for normal in [Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0)].iter() {
+        let n = material.shading_normal(&uv, normal, &tangents);
+        assert!((Vec3::dot(&n, normal) - 1.0).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_whitted_weights() {
+    assert_eq!(Material::WATER.reflectance(), 0.2);
+    assert_eq!(Material::GLASS.reflectance(), 0.05);
+    assert_eq!(Material::GLASS.refraction(), 1.6);
+    assert_eq!(Material::RUBBER.diffuse(), 0.5);
+    // without fixed weights they come from the bsdf
+    let water = Material {
+        whitted: None,
+        ..Material::WATER
+    };
+    assert!((water.reflectance() - fresnel_dielectric(1.0, 1.33)).abs() < 1e-12);
+    assert_eq!(water.refraction(), 1.33);
+}
@@ -0,0 +1,101 @@
+use super::object::Object;
+use crate::Material;
+use crate::Vec2;
+use crate::Vec3;
+use std::sync::Arc;
+
+/**
+ * indexed triangle mesh. `normals` and `uvs` are either empty or have one entry per position.
+ */
+#[derive(Debug)]
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+    pub indices: Vec<[usize; 3]>,
+    pub material: Material,
+    pub color: Vec3,
+}
+
+/**
+ * one face of a mesh, this is what goes into the bvh
+ */
+#[derive(Debug, Clone)]
+pub struct Triangle {
+    pub mesh: Arc<Mesh>,
+    pub face: usize,
+}
+
+impl Triangle {
+    pub fn vertices(&self) -> [Vec3; 3] {
+        let [a, b, c] = self.mesh.indices[self.face];
+        [
+            self.mesh.positions[a],
+            self.mesh.positions[b],
+            self.mesh.positions[c],
+        ]
+    }
+    /**
+     * interpolated vertex normal at barycentric (u, v), falls back to the face normal
+     */
+    pub fn shading_normal(&self, u: f64, v: f64) -> Vec3 {
+        let [a, b, c] = self.mesh.indices[self.face];
+        let mut normal = if self.mesh.normals.is_empty() {
+            let [p0, p1, p2] = self.vertices();
+            Vec3::cross(&(p1 - p0), &(p2 - p0))
+        } else {
+            self.mesh.normals[a] * (1.0 - u - v) + self.mesh.normals[b] * u + self.mesh.normals[c] * v
+        };
+        normal.normalize();
+        normal
+    }
+    /**
+     * directions of growing texture u and v across the face, any two edges if the mesh has no uvs
+     */
+    pub fn tangents(&self) -> (Vec3, Vec3) {
+        let [p0, p1, p2] = self.vertices();
+        let (e1, e2) = (p1 - p0, p2 - p0);
+        let normal = Vec3::cross(&e1, &e2);
+        if !self.mesh.uvs.is_empty() {
+            let [a, b, c] = self.mesh.indices[self.face];
+            let (uv0, uv1, uv2) = (self.mesh.uvs[a], self.mesh.uvs[b], self.mesh.uvs[c]);
+            let (du1, dv1) = (uv1.x() - uv0.x(), uv1.y() - uv0.y());
+            let (du2, dv2) = (uv2.x() - uv0.x(), uv2.y() - uv0.y());
+            let det = du1 * dv2 - du2 * dv1;
+            if det.abs() > 1e-12 {
+                return ((e1 * dv2 - e2 * dv1) / det, (e2 * du1 - e1 * du2) / det);
+            }
+        }
+        (e1, Vec3::cross(&normal, &e1))
+    }
+    /**
+     * interpolated uv at barycentric (u, v), (0, 0) if the mesh has no uvs
+     */
+    pub fn uv(&self, u: f64, v: f64) -> Vec2 {
+        if self.mesh.uvs.is_empty() {
+            return Vec2::ORIGIN;
+        }
+        let [a, b, c] = self.mesh.indices[self.face];
+        let w = 1.0 - u - v;
+        let (uv0, uv1, uv2) = (self.mesh.uvs[a], self.mesh.uvs[b], self.mesh.uvs[c]);
+        Vec2::new(
+            uv0.x() * w + uv1.x() * u + uv2.x() * v,
+            uv0.y() * w + uv1.y() * u + uv2.y() * v,
+        )
+    }
+}
+
+impl Object for Triangle {
+    fn bounding_box(&self) -> (Vec3, Vec3) {
+        let [p0, p1, p2] = self.vertices();
+        let mut min = p0;
+        let mut max = p0;
+        for p in [p1, p2].iter() {
+            for i in 0..3 {
+                min.value[i] = min.value[i].min(p.value[i]);
+                max.value[i] = max.value[i].max(p.value[i]);
+            }
+        }
+        (min, max)
+    }
+}
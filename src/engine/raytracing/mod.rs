@@ -5,9 +5,22 @@ mod material;
 // mod noise;
 mod bvh;
 mod object;
+mod mesh;
+mod obj_loader;
+mod light;
+mod bsdf;
+mod integrator;
+mod tile;
+mod sampler;
 
 pub use scene::*;
 pub use ray::*;
 pub use material::*;
 pub use bvh::*;
+pub use mesh::*;
+pub use light::*;
+pub use bsdf::*;
+pub use integrator::*;
+pub use tile::*;
+pub use sampler::*;
 // pub use noise::*;
\ No newline at end of file
@@ -0,0 +1,290 @@
+use crate::BsdfModel;
+use crate::Material;
+use crate::Principled;
+use crate::Mesh;
+use crate::Scene;
+use crate::Texture;
+use crate::TextureError;
+use crate::Vec2;
+use crate::Vec3;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/**
+ * a file referenced by an obj that could not be loaded, the scene was loaded without it
+ */
+#[derive(Debug)]
+pub enum MissingAsset {
+    MaterialLibrary(PathBuf, tobj::LoadError),
+    Texture(PathBuf, TextureError),
+}
+
+impl Scene {
+    /**
+     * builds a scene from an obj file, `mtllib`s are resolved relative to the obj file.
+     * a missing or broken mtl or texture file only drops its materials or maps, the geometry
+     * still loads and the failures are returned next to the scene.
+     */
+    pub fn load_obj<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<(Scene, Vec<MissingAsset>), tobj::LoadError> {
+        let mut scene = Scene::new();
+        let missing = scene.add_obj(path)?;
+        Ok((scene, missing))
+    }
+
+    pub fn add_obj<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Vec<MissingAsset>, tobj::LoadError> {
+        let path = path.as_ref();
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let file = std::fs::File::open(path).map_err(|_| tobj::LoadError::OpenFileFailed)?;
+        let mut reader = std::io::BufReader::new(file);
+        // the mtl callback is a `Fn`
+        let missing = RefCell::new(vec![]);
+        let (models, materials) = tobj::load_obj_buf(&mut reader, true, |mtl_path| {
+            let full_path = base_dir.join(mtl_path);
+            tobj::load_mtl(&full_path).or_else(|err| {
+                missing.borrow_mut().push(MissingAsset::MaterialLibrary(full_path, err));
+                Ok((vec![], HashMap::new()))
+            })
+        })?;
+
+        // materials often share their texture files
+        let mut textures = HashMap::new();
+        for model in models.into_iter() {
+            let mesh = model.mesh;
+            let (material, color) = match mesh.material_id.and_then(|id| materials.get(id)) {
+                Some(mtl) => {
+                    let (mut material, color) = material_from_mtl(mtl);
+                    let mut texture = |name: &str| {
+                        load_texture(base_dir, name, &mut textures, &mut missing.borrow_mut())
+                    };
+                    material.albedo_map = texture(&mtl.diffuse_texture);
+                    material.normal_map = texture(&mtl.normal_texture);
+                    // `map_Pr` from the pbr extension of mtl
+                    material.roughness_map = mtl.unknown_param.get("map_Pr").and_then(|name| texture(name));
+                    (material, color)
+                }
+                None => (Material::RUBBER, Vec3::WHITE),
+            };
+            self.add_mesh(Mesh {
+                positions: mesh
+                    .positions
+                    .chunks(3)
+                    .map(|p| Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+                    .collect(),
+                normals: mesh
+                    .normals
+                    .chunks(3)
+                    .map(|n| Vec3::new(n[0] as f64, n[1] as f64, n[2] as f64))
+                    .collect(),
+                uvs: mesh
+                    .texcoords
+                    .chunks(2)
+                    .map(|uv| Vec2::new(uv[0] as f64, uv[1] as f64))
+                    .collect(),
+                indices: mesh
+                    .indices
+                    .chunks(3)
+                    .map(|f| [f[0] as usize, f[1] as usize, f[2] as usize])
+                    .collect(),
+                material,
+                color,
+            });
+        }
+        Ok(missing.into_inner())
+    }
+}
+
+/**
+ * maps an mtl material onto our material and surface color.
+ * see http://paulbourke.net/dataformats/mtl/ for the meaning of `illum`
+ */
+fn material_from_mtl(mtl: &tobj::Material) -> (Material, Vec3) {
+    let to_vec3 = |v: [f32; 3]| Vec3::new(v[0] as f64, v[1] as f64, v[2] as f64);
+    let max_component = |v: &Vec3| v.x().max(v.y()).max(v.z());
+
+    let kd = to_vec3(mtl.diffuse);
+    let ks = to_vec3(mtl.specular);
+    // tobj does not know Ke, it ends up with the unknown params
+    let emission = mtl
+        .unknown_param
+        .get("Ke")
+        .map(|value| {
+            let v: Vec<f64> = value
+                .split_whitespace()
+                .filter_map(|x| x.parse().ok())
+                .collect();
+            match v.len() {
+                0 => Vec3::BLACK,
+                1 | 2 => Vec3::new(v[0], v[0], v[0]),
+                _ => Vec3::new(v[0], v[1], v[2]),
+            }
+        })
+        .unwrap_or(Vec3::BLACK);
+
+    let illum = mtl.illumination_model.unwrap_or(2);
+    let opacity = (mtl.dissolve as f64).clamp(0.0, 1.0);
+    let is_transparent = opacity < 1.0 || [4, 6, 7, 9].contains(&illum);
+    let has_specular = illum >= 2 && max_component(&ks) > 0.0;
+    // a material with only a specular color is a metal
+    let is_metal = max_component(&kd) <= 0.0 && has_specular;
+
+    let transmission = if !is_transparent {
+        0.0
+    } else if opacity < 1.0 {
+        1.0 - opacity
+    } else {
+        1.0
+    };
+    // phong exponent to a beckmann-like alpha, Ns of 0 is fully rough
+    let alpha = (2.0 / (mtl.shininess as f64 + 2.0)).sqrt();
+
+    // the color goes to the primitive, it tints the whole bsdf
+    let color = if max_component(&kd) > 0.0 {
+        kd
+    } else if max_component(&ks) > 0.0 {
+        ks
+    } else {
+        Vec3::WHITE
+    };
+
+    (
+        Material {
+            bsdf: BsdfModel::Principled(Principled {
+                base_color: Vec3::WHITE,
+                metallic: if is_metal { 1.0 } else { 0.0 },
+                roughness: alpha.sqrt(),
+                specular: if has_specular { 0.04 } else { 0.0 },
+                transmission,
+                ior: (mtl.optical_density as f64).max(1.0),
+            }),
+            emission,
+            albedo_map: None,
+            roughness_map: None,
+            normal_map: None,
+            whitted: None,
+        },
+        color,
+    )
+}
+
+/**
+ * `name` is the value of a `map_*` statement, see `map_file_name`.
+ * a texture that fails to load is added to `missing` once and skipped, like a missing mtl file
+ */
+fn load_texture(
+    base_dir: &Path,
+    name: &str,
+    cache: &mut HashMap<String, Option<Arc<Texture>>>,
+    missing: &mut Vec<MissingAsset>,
+) -> Option<Arc<Texture>> {
+    let file_name = map_file_name(name)?;
+    cache
+        .entry(file_name.to_string())
+        .or_insert_with(|| {
+            let full_path = base_dir.join(file_name);
+            match Texture::load(&full_path) {
+                Ok(texture) => Some(Arc::new(texture)),
+                Err(err) => {
+                    missing.push(MissingAsset::Texture(full_path, err));
+                    None
+                }
+            }
+        })
+        .clone()
+}
+
+/**
+ * the file name of a `map_*` statement. options like `-bm 1` or `-o 0.5 0.5` come before it,
+ * the rest of the line is the name and may contain spaces
+ */
+fn map_file_name(statement: &str) -> Option<&str> {
+    let mut rest = statement.trim();
+    while rest.starts_with('-') {
+        let (option, mut args) = split_word(rest);
+        let count = match option {
+            "-mm" => 2,
+            // one to three numbers
+            "-o" | "-s" | "-t" => 3,
+            _ => 1,
+        };
+        for _ in 0..count {
+            let (word, next) = split_word(args);
+            if count == 3 && word.parse::<f64>().is_err() {
+                break;
+            }
+            args = next;
+        }
+        rest = args;
+    }
+    if rest.is_empty() {
+        None
+    } else {
+        Some(rest)
+    }
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(end) => (&text[..end], text[end..].trim_start()),
+        None => (text, ""),
+    }
+}
+
+#[test]
+fn test_map_file_name() {
+    assert_eq!(map_file_name("wood.png"), Some("wood.png"));
+    assert_eq!(map_file_name("has spaces.jpg"), Some("has spaces.jpg"));
+    assert_eq!(map_file_name("-bm 0.5 -o 1 2 normal map.png"), Some("normal map.png"));
+    assert_eq!(map_file_name("-s 2 -clamp on tile.png"), Some("tile.png"));
+    assert_eq!(map_file_name("-bm 1"), None);
+}
+
+#[test]
+fn test_load_cornell_box() {
+    use crate::Primitive;
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("cornell_box.obj");
+    let (scene, missing) = Scene::load_obj(path).unwrap();
+
+    assert_eq!(scene.objects.len(), 36, "18 quads should become 36 triangles");
+    assert!(
+        missing.iter().any(|asset| matches!(asset,
+            MissingAsset::MaterialLibrary(path, _) if path.ends_with("cornell_box2.mtl"))),
+        "the missing mtllib should be reported"
+    );
+    assert!(
+        missing.iter().any(|asset| matches!(asset,
+            MissingAsset::Texture(path, _)
+                if path.ends_with("this diffuse texture has spaces.jpg"))),
+        "the missing maps should be reported"
+    );
+
+    let red_faces = scene
+        .objects
+        .iter()
+        .filter(|primitive| match primitive {
+            Primitive::Triangle(triangle) => {
+                let color = triangle.mesh.color;
+                color.x() == 1.0 && color.y() == 0.0 && color.z() == 0.0
+            }
+            _ => false,
+        })
+        .count();
+    assert!(red_faces > 0, "the red wall should keep its Kd");
+
+    assert!(
+        scene
+            .objects
+            .iter()
+            .any(|primitive| primitive.material().emission.x() > 0.0),
+        "white has a Ke and should be emissive"
+    );
+}
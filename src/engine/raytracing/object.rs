@@ -1,5 +1,8 @@
 use crate::Vec3;
 
 pub trait Object {
-    fn bounding_box() -> (Vec3, Vec3);
-}
\ No newline at end of file
+    /**
+     * axis aligned bounding box, as (min, max)
+     */
+    fn bounding_box(&self) -> (Vec3, Vec3);
+}
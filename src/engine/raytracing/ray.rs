@@ -1,6 +1,9 @@
 use super::Material;
+use crate::Primitive;
 use crate::Scene;
 use crate::Sphere;
+use crate::Triangle;
+use crate::Vec2;
 use crate::Vec4;
 use crate::Vec3;
 
@@ -12,111 +15,250 @@ pub struct Ray {
 
 #[derive(Debug)]
 pub struct IntersectionResult<'a> {
-    pub sphere: &'a Sphere,
+    pub primitive: &'a Primitive,
+    /**
+     * index of the primitive in `Scene::objects`
+     */
+    pub primitive_index: usize,
     pub point: Vec4,
+    /**
+     * always faces the incoming ray
+     */
     pub normal: Vec4,
     pub refraction_ratio: f64,
+    /**
+     * false when the ray hits the surface from inside the object
+     */
+    pub front_face: bool,
+    pub uv: Vec2,
 }
 
 impl Ray {
     const NEAR_DISTANCE: f64 = 0.001;
     const FAR_DISTANCE: f64 = f64::INFINITY;
     pub fn intersect<'a>(&self, scene: &'a Scene) -> Option<IntersectionResult<'a>> {
-        /*
-         * 问题: 没有排序
-         */
+        if let Some(bvh) = &scene.bvh {
+            return bvh.intersect(&scene.objects, self, |index, primitive, _| {
+                self.intersect_primitive(index, primitive)
+            });
+        }
 
         let mut res: Option<IntersectionResult> = None;
         let mut cur_nearest = Ray::FAR_DISTANCE;
 
-        for sphere in scene.objects.iter() {
-            let ro = sphere.origin - self.origin;
-
-            if ro.length() - sphere.radius < -Ray::NEAR_DISTANCE {
-                //射线在球里面
-                /*
-                 * inside
-                 *
-                 *
-                 *    r----d------p  this is the ray --->
-                 *         |     / \
-                 *         |    /   \
-                 *         |   /     reflection
-                 *         |  /
-                 *         | /
-                 *         |/
-                 *         o this is the sphere origin
-                 */
-                let dist_rd = ro * self.dir;
-                let rd = self.dir * dist_rd;
-                let od = rd - ro;
-                let dist_od = (od * od).sqrt();
-
-                let sine_inc = dist_od / sphere.radius;
-                let dist_pd = sphere.radius * sine_inc.acos().sin();
-                let dp = self.dir * dist_pd;
-                let op = od + dp;
-                let p = sphere.origin + op;
-                let normal = (op * -1.0).normalize();
-                let refraction_ratio = sphere.material.refraction;
-
-                res = Some(IntersectionResult {
-                    sphere,
-                    point: p,
-                    normal,
-                    refraction_ratio,
-                });
-                break;
+        for (index, primitive) in scene.objects.iter().enumerate() {
+            if let Some((distance, intersection)) = self.intersect_primitive(index, primitive) {
+                if distance < cur_nearest {
+                    res = Some(intersection);
+                    cur_nearest = distance;
+                }
             }
+        }
+        res
+    }
+    /**
+     * whether anything is hit closer than `max_distance`, used for shadow rays
+     */
+    pub fn occluded(&self, scene: &Scene, max_distance: f64) -> bool {
+        match self.intersect(scene) {
+            Some(intersection) => (intersection.point - self.origin).length() < max_distance,
+            None => false,
+        }
+    }
+    /**
+     * returns the distance from the ray origin to the hit point, along with the hit
+     */
+    fn intersect_primitive<'a>(
+        &self,
+        primitive_index: usize,
+        primitive: &'a Primitive,
+    ) -> Option<(f64, IntersectionResult<'a>)> {
+        match primitive {
+            Primitive::Sphere(sphere) => self.intersect_sphere(primitive_index, primitive, sphere),
+            Primitive::Triangle(triangle) => {
+                self.intersect_triangle(primitive_index, primitive, triangle)
+            }
+        }
+    }
+    fn intersect_sphere<'a>(
+        &self,
+        primitive_index: usize,
+        primitive: &'a Primitive,
+        sphere: &Sphere,
+    ) -> Option<(f64, IntersectionResult<'a>)> {
+        let ro = sphere.origin - self.origin;
 
+        if ro.length() - sphere.radius < -Ray::NEAR_DISTANCE {
+            //射线在球里面
             /*
-             * 在球外面
-             *        l
-             *       / <- this is reflection
-             *     /
-             * r-p------d-------  this is the ray --->
-             *    \     |
-             *     \    |
-             *      \   |
-             *       \  |
-             *        \ |
-             *         \|
-             *          o this is the sphere origin
+             * inside
+             *
+             *
+             *    r----d------p  this is the ray --->
+             *         |     / \
+             *         |    /   \
+             *         |   /     reflection
+             *         |  /
+             *         | /
+             *         |/
+             *         o this is the sphere origin
              */
             let dist_rd = ro * self.dir;
-            if dist_rd > Ray::NEAR_DISTANCE {
-                let rd = self.dir * dist_rd;
-                let od = rd - ro;
-                let dist_od = (od * od).sqrt();
-                let sine_inc = dist_od / sphere.radius;
-                let dist_pd = sphere.radius * sine_inc.acos().sin();
-                let dist_rp = dist_rd - dist_pd;
-                if dist_rp < cur_nearest {
-                    let dp = self.dir * dist_pd * -1.0;
-                    let op = od + dp;
-                    let p = sphere.origin + op;
-                    let normal = op.normalize();
-
-                    let refraction_ratio = 1.0 / sphere.material.refraction;
-
-                    res = Some(IntersectionResult {
-                        sphere,
-                        point: p,
-                        normal,
-                        refraction_ratio,
-                    });
-                    cur_nearest = dist_rp;
-                }
-            }
+            let rd = self.dir * dist_rd;
+            let od = rd - ro;
+            let dist_od = (od * od).sqrt();
+
+            // r * sin(acos(od / r)), without losing precision near the silhouette
+            let dist_pd = (sphere.radius * sphere.radius - dist_od * dist_od).max(0.0).sqrt();
+            let dp = self.dir * dist_pd;
+            let op = od + dp;
+            let p = sphere.origin + op;
+            let normal = (op * -1.0).normalize();
+            let refraction_ratio = sphere.material.refraction();
+
+            return Some((
+                dist_rd + dist_pd,
+                IntersectionResult {
+                    primitive,
+                    primitive_index,
+                    point: p,
+                    normal,
+                    refraction_ratio,
+                    front_face: false,
+                    uv: sphere_uv(&op.normalize()),
+                },
+            ));
         }
-        res
+
+        /*
+         * 在球外面
+         *        l
+         *       / <- this is reflection
+         *     /
+         * r-p------d-------  this is the ray --->
+         *    \     |
+         *     \    |
+         *      \   |
+         *       \  |
+         *        \ |
+         *         \|
+         *          o this is the sphere origin
+         */
+        let dist_rd = ro * self.dir;
+        if dist_rd <= Ray::NEAR_DISTANCE {
+            return None;
+        }
+        let rd = self.dir * dist_rd;
+        let od = rd - ro;
+        let dist_od = (od * od).sqrt();
+        if dist_od > sphere.radius {
+            return None;
+        }
+        let dist_pd = (sphere.radius * sphere.radius - dist_od * dist_od).sqrt();
+        let dist_rp = dist_rd - dist_pd;
+        let dp = self.dir * dist_pd * -1.0;
+        let op = od + dp;
+        let p = sphere.origin + op;
+        let normal = op.normalize();
+
+        let refraction_ratio = 1.0 / sphere.material.refraction();
+
+        Some((
+            dist_rp,
+            IntersectionResult {
+                primitive,
+                primitive_index,
+                point: p,
+                normal,
+                refraction_ratio,
+                front_face: true,
+                uv: sphere_uv(&normal),
+            },
+        ))
+    }
+    /**
+     * Möller–Trumbore
+     */
+    fn intersect_triangle<'a>(
+        &self,
+        primitive_index: usize,
+        primitive: &'a Primitive,
+        triangle: &Triangle,
+    ) -> Option<(f64, IntersectionResult<'a>)> {
+        const EPSILON: f64 = 1e-12;
+        let [p0, p1, p2] = triangle.vertices();
+        let origin = self.origin.xyz();
+        let dir = self.dir.xyz();
+
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let p_vec = Vec3::cross(&dir, &e2);
+        let det = Vec3::dot(&e1, &p_vec);
+        if det.abs() < EPSILON {
+            // parallel to the triangle
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let t_vec = origin - p0;
+        let u = Vec3::dot(&t_vec, &p_vec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q_vec = Vec3::cross(&t_vec, &e1);
+        let v = Vec3::dot(&dir, &q_vec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = Vec3::dot(&e2, &q_vec) * inv_det;
+        if distance <= Ray::NEAR_DISTANCE {
+            return None;
+        }
+
+        let point = origin + dir * distance;
+        let geometric_normal = Vec3::cross(&e1, &e2);
+        let mut normal = triangle.shading_normal(u, v);
+        let material = &triangle.mesh.material;
+        let front_face = Vec3::dot(&dir, &geometric_normal) <= 0.0;
+        let refraction_ratio = if !front_face {
+            // hit from the back, we are leaving the mesh
+            material.refraction()
+        } else {
+            1.0 / material.refraction()
+        };
+        if Vec3::dot(&dir, &normal) > 0.0 {
+            normal = normal * -1.0;
+        }
+
+        Some((
+            distance,
+            IntersectionResult {
+                primitive,
+                primitive_index,
+                point: Vec4::new(point.x(), point.y(), point.z(), 1.0),
+                normal: Vec4::new(normal.x(), normal.y(), normal.z(), 1.0),
+                refraction_ratio,
+                front_face,
+                uv: triangle.uv(u, v),
+            },
+        ))
     }
 }
 
+/**
+ * equirectangular mapping of a point on the unit sphere, v grows towards +y like texture coordinates
+ */
+fn sphere_uv(normal: &Vec4) -> Vec2 {
+    let n = normal.xyz();
+    Vec2::new(
+        0.5 + n.z().atan2(n.x()) / (2.0 * std::f64::consts::PI),
+        0.5 + n.y().clamp(-1.0, 1.0).asin() / std::f64::consts::PI,
+    )
+}
+
 #[test]
 fn test_intersect_ray() {
     let epsilon = 1e-7;
-    let mut scene = Scene { objects: vec![] };
+    let mut scene = Scene::new();
     scene.add_sphere(Vec4::ORIGIN, 11.0, Material::GLASS, Vec3::ORIGIN);
 
     {
@@ -154,3 +296,52 @@ fn test_intersect_ray() {
         assert_eq!(res.unwrap().point.x(), 5.0);
     }
 }
+
+#[test]
+fn test_intersect_triangle() {
+    use crate::Mesh;
+    let epsilon = 1e-9;
+    let mut scene = Scene::new();
+    scene.add_mesh(Mesh {
+        positions: vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(10.0, 0.0, 0.0),
+            Vec3::new(0.0, 10.0, 0.0),
+        ],
+        normals: vec![],
+        uvs: vec![Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(0.0, 1.0)],
+        indices: vec![[0, 1, 2]],
+        material: Material::GLASS,
+        color: Vec3::WHITE,
+    });
+
+    {
+        let ray = Ray {
+            origin: Vec4::new(2.0, 3.0, 5.0, 1.0),
+            dir: Vec4::new(0.0, 0.0, -1.0, 1.0),
+        };
+        let res = ray.intersect(&scene).expect("should hit the front face");
+        assert!(res.point.z().abs() < epsilon);
+        assert!((res.normal.z() - 1.0).abs() < epsilon);
+        assert!((res.uv.x() - 0.2).abs() < epsilon && (res.uv.y() - 0.3).abs() < epsilon);
+        assert!((res.refraction_ratio - 1.0 / Material::GLASS.refraction()).abs() < epsilon);
+    }
+
+    {
+        let ray = Ray {
+            origin: Vec4::new(2.0, 3.0, -5.0, 1.0),
+            dir: Vec4::new(0.0, 0.0, 1.0, 1.0),
+        };
+        let res = ray.intersect(&scene).expect("should hit the back face");
+        assert!((res.normal.z() + 1.0).abs() < epsilon);
+        assert!((res.refraction_ratio - Material::GLASS.refraction()).abs() < epsilon);
+    }
+
+    {
+        let ray = Ray {
+            origin: Vec4::new(8.0, 8.0, 5.0, 1.0),
+            dir: Vec4::new(0.0, 0.0, -1.0, 1.0),
+        };
+        assert!(ray.intersect(&scene).is_none());
+    }
+}
@@ -0,0 +1,569 @@
+use std::sync::OnceLock;
+
+/**
+ * sample points in [0, 1) for Monte Carlo integration.
+ * a sample is a pure function of (pixel, sample index, dimension), so every pixel and every
+ * dimension gets its own reproducible stream, whatever thread or order it is rendered in
+ */
+pub trait Sampler: Sync {
+    fn samples_per_pixel(&self) -> usize;
+    fn sample_1d(&self, pixel: (usize, usize), index: usize, dimension: usize) -> f64;
+    /**
+     * uses `dimension` and `dimension + 1`, samplers that stratify in 2d override this
+     */
+    fn sample_2d(&self, pixel: (usize, usize), index: usize, dimension: usize) -> (f64, f64) {
+        (
+            self.sample_1d(pixel, index, dimension),
+            self.sample_1d(pixel, index, dimension + 1),
+        )
+    }
+}
+
+/**
+ * walks the dimensions of one pixel sample, this is what the camera and the integrators draw from
+ */
+pub struct SampleStream<'a> {
+    sampler: &'a dyn Sampler,
+    pixel: (usize, usize),
+    index: usize,
+    dimension: usize,
+}
+
+impl<'a> SampleStream<'a> {
+    pub fn new(sampler: &'a dyn Sampler, pixel: (usize, usize), index: usize) -> SampleStream<'a> {
+        SampleStream {
+            sampler,
+            pixel,
+            index,
+            dimension: 0,
+        }
+    }
+    pub fn get_1d(&mut self) -> f64 {
+        let res = self
+            .sampler
+            .sample_1d(self.pixel, self.index, self.dimension);
+        self.dimension += 1;
+        res
+    }
+    pub fn get_2d(&mut self) -> (f64, f64) {
+        let res = self
+            .sampler
+            .sample_2d(self.pixel, self.index, self.dimension);
+        self.dimension += 2;
+        res
+    }
+    /**
+     * a 2d sample for the direction and a 1d one for picking a lobe or a light
+     */
+    pub fn get_3d(&mut self) -> (f64, f64, f64) {
+        let (u0, u1) = self.get_2d();
+        (self.get_1d(), u0, u1)
+    }
+}
+
+/**
+ * uniform random numbers, no stratification at all
+ */
+#[derive(Debug, Clone)]
+pub struct IndependentSampler {
+    pub samples_per_pixel: usize,
+    pub seed: u64,
+}
+
+impl Sampler for IndependentSampler {
+    fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+    fn sample_1d(&self, pixel: (usize, usize), index: usize, dimension: usize) -> f64 {
+        to_unit(hash(&[
+            self.seed,
+            pixel_key(pixel),
+            index as u64,
+            dimension as u64,
+        ]))
+    }
+}
+
+/**
+ * splits every 1d dimension into `samples_per_pixel` strata and every 2d one into an
+ * `x_strata` by `y_strata` grid, the strata are visited in a shuffled order per pixel and dimension
+ */
+#[derive(Debug, Clone)]
+pub struct StratifiedSampler {
+    pub x_strata: usize,
+    pub y_strata: usize,
+    /**
+     * false puts every sample in the middle of its stratum
+     */
+    pub jitter: bool,
+    pub seed: u64,
+}
+
+impl StratifiedSampler {
+    fn offset(&self, key: u64) -> f64 {
+        if self.jitter {
+            to_unit(mix_bits(key ^ 0x5851_f42d_4c95_7f2d))
+        } else {
+            0.5
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn samples_per_pixel(&self) -> usize {
+        self.x_strata * self.y_strata
+    }
+    fn sample_1d(&self, pixel: (usize, usize), index: usize, dimension: usize) -> f64 {
+        let count = self.samples_per_pixel();
+        // every `count` samples start a new round over the strata
+        let round = (index / count) as u64;
+        let key = hash(&[self.seed, pixel_key(pixel), dimension as u64, round]);
+        let stratum = permutation_element(index % count, count, key);
+        (stratum as f64 + self.offset(hash(&[key, index as u64]))) / count as f64
+    }
+    fn sample_2d(&self, pixel: (usize, usize), index: usize, dimension: usize) -> (f64, f64) {
+        let count = self.samples_per_pixel();
+        let round = (index / count) as u64;
+        let key = hash(&[self.seed, pixel_key(pixel), dimension as u64, round]);
+        let stratum = permutation_element(index % count, count, key);
+        let (x, y) = (stratum % self.x_strata, stratum / self.x_strata);
+        (
+            (x as f64 + self.offset(hash(&[key, index as u64, 0]))) / self.x_strata as f64,
+            (y as f64 + self.offset(hash(&[key, index as u64, 1]))) / self.y_strata as f64,
+        )
+    }
+}
+
+/**
+ * the Halton sequence, one prime base per dimension, shifted randomly per pixel.
+ * dimensions past the prime table fall back to independent samples
+ */
+#[derive(Debug, Clone)]
+pub struct HaltonSampler {
+    pub samples_per_pixel: usize,
+    pub seed: u64,
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+impl Sampler for HaltonSampler {
+    fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+    fn sample_1d(&self, pixel: (usize, usize), index: usize, dimension: usize) -> f64 {
+        let key = hash(&[self.seed, pixel_key(pixel), dimension as u64]);
+        match PRIMES.get(dimension) {
+            Some(base) => {
+                // Cranley-Patterson rotation, keeps the stratification of the sequence
+                (radical_inverse(index as u64, *base) + to_unit(key)).fract()
+            }
+            None => to_unit(hash(&[key, index as u64])),
+        }
+    }
+}
+
+/**
+ * the first two dimensions of the Sobol sequence, Owen scrambled and with the sample order
+ * shuffled per pixel and dimension pair, so higher dimensions stay uncorrelated
+ */
+#[derive(Debug, Clone)]
+pub struct SobolSampler {
+    pub samples_per_pixel: usize,
+    pub seed: u64,
+}
+
+impl Sampler for SobolSampler {
+    fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+    fn sample_1d(&self, pixel: (usize, usize), index: usize, dimension: usize) -> f64 {
+        let key = hash(&[self.seed, pixel_key(pixel), dimension as u64]);
+        let index = self.shuffled_index(index, key);
+        to_unit_u32(owen_scramble(index.reverse_bits(), key as u32))
+    }
+    fn sample_2d(&self, pixel: (usize, usize), index: usize, dimension: usize) -> (f64, f64) {
+        let key = hash(&[self.seed, pixel_key(pixel), dimension as u64]);
+        let index = self.shuffled_index(index, key);
+        (
+            to_unit_u32(owen_scramble(index.reverse_bits(), key as u32)),
+            to_unit_u32(owen_scramble(
+                sobol_second_dimension(index),
+                (key >> 32) as u32,
+            )),
+        )
+    }
+}
+
+impl SobolSampler {
+    fn shuffled_index(&self, index: usize, key: u64) -> u32 {
+        let count = self.samples_per_pixel.max(1);
+        let round = index / count;
+        (round * count + permutation_element(index % count, count, mix_bits(key))) as u32
+    }
+}
+
+/**
+ * a 64x64 blue noise tile, offset per dimension, plus a golden ratio step per sample index.
+ * the error of neighbouring pixels is negatively correlated, so the remaining noise looks finer
+ */
+#[derive(Debug, Clone)]
+pub struct BlueNoiseSampler {
+    pub samples_per_pixel: usize,
+    pub seed: u64,
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+impl Sampler for BlueNoiseSampler {
+    fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+    fn sample_1d(&self, pixel: (usize, usize), index: usize, dimension: usize) -> f64 {
+        // 1 / golden ratio
+        (self.tile_value(pixel, dimension) + index as f64 * 0.618_033_988_749_894_9).fract()
+    }
+    /**
+     * the R2 sequence over the sample index, two golden ratio steps would put the samples on a line
+     */
+    fn sample_2d(&self, pixel: (usize, usize), index: usize, dimension: usize) -> (f64, f64) {
+        (
+            (self.tile_value(pixel, dimension) + index as f64 * 0.754_877_666_246_692_7).fract(),
+            (self.tile_value(pixel, dimension + 1) + index as f64 * 0.569_840_290_998_053_2)
+                .fract(),
+        )
+    }
+}
+
+impl BlueNoiseSampler {
+    fn tile_value(&self, pixel: (usize, usize), dimension: usize) -> f64 {
+        let key = hash(&[self.seed, dimension as u64]);
+        let x = (pixel.0 + key as usize % BLUE_NOISE_SIZE) % BLUE_NOISE_SIZE;
+        let y = (pixel.1 + (key >> 32) as usize % BLUE_NOISE_SIZE) % BLUE_NOISE_SIZE;
+        blue_noise_tile()[y * BLUE_NOISE_SIZE + x]
+    }
+}
+
+/**
+ * Ulichney's void and cluster method, every pixel of the tile gets its rank as a value in [0, 1)
+ */
+fn blue_noise_tile() -> &'static [f64] {
+    static TILE: OnceLock<Vec<f64>> = OnceLock::new();
+    TILE.get_or_init(|| {
+        const N: usize = BLUE_NOISE_SIZE;
+        const RADIUS: isize = 6;
+        const SIGMA: f64 = 1.5;
+
+        // gaussian energy of the set pixels, wrapping around the tile
+        let update = |energy: &mut [f64], index: usize, sign: f64| {
+            let (x, y) = ((index % N) as isize, (index / N) as isize);
+            for dy in -RADIUS..=RADIUS {
+                for dx in -RADIUS..=RADIUS {
+                    let weight = (-((dx * dx + dy * dy) as f64) / (2.0 * SIGMA * SIGMA)).exp();
+                    let nx = (x + dx).rem_euclid(N as isize) as usize;
+                    let ny = (y + dy).rem_euclid(N as isize) as usize;
+                    energy[ny * N + nx] += weight * sign;
+                }
+            }
+        };
+        let tightest_cluster = |energy: &[f64], pattern: &[bool]| {
+            (0..N * N)
+                .filter(|i| pattern[*i])
+                .max_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+                .unwrap()
+        };
+        let largest_void = |energy: &[f64], pattern: &[bool]| {
+            (0..N * N)
+                .filter(|i| !pattern[*i])
+                .min_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+                .unwrap()
+        };
+
+        // a random initial pattern with a tenth of the pixels set
+        let mut pattern = vec![false; N * N];
+        let mut energy = vec![0.0; N * N];
+        let mut ones = 0;
+        for (i, set) in pattern.iter_mut().enumerate() {
+            if to_unit(hash(&[0xb10e, i as u64])) < 0.1 {
+                *set = true;
+                update(&mut energy, i, 1.0);
+                ones += 1;
+            }
+        }
+        // move points from clusters to voids until that does not change anything
+        loop {
+            let cluster = tightest_cluster(&energy, &pattern);
+            pattern[cluster] = false;
+            update(&mut energy, cluster, -1.0);
+            let void = largest_void(&energy, &pattern);
+            pattern[void] = true;
+            update(&mut energy, void, 1.0);
+            if void == cluster {
+                break;
+            }
+        }
+
+        let mut rank = vec![0; N * N];
+        // ranks below the initial pattern, removing clusters
+        {
+            let (mut pattern, mut energy) = (pattern.clone(), energy.clone());
+            for r in (0..ones).rev() {
+                let cluster = tightest_cluster(&energy, &pattern);
+                pattern[cluster] = false;
+                update(&mut energy, cluster, -1.0);
+                rank[cluster] = r;
+            }
+        }
+        // ranks above it, filling voids
+        for r in ones..N * N {
+            let void = largest_void(&energy, &pattern);
+            pattern[void] = true;
+            update(&mut energy, void, 1.0);
+            rank[void] = r;
+        }
+        rank.into_iter()
+            .map(|r| (r as f64 + 0.5) / (N * N) as f64)
+            .collect()
+    })
+}
+
+fn pixel_key(pixel: (usize, usize)) -> u64 {
+    ((pixel.0 as u64) << 32) | pixel.1 as u64
+}
+
+/**
+ * splitmix64 finalizer
+ */
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0x9e37_79b9_7f4a_7c15, |res, v| mix_bits(res ^ mix_bits(*v)))
+}
+
+fn to_unit(v: u64) -> f64 {
+    // the top 53 bits fit a double exactly
+    (v >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn to_unit_u32(v: u32) -> f64 {
+    v as f64 / (1u64 << 32) as f64
+}
+
+fn radical_inverse(mut index: u64, base: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut reversed = 0;
+    while index > 0 {
+        reversed = reversed * base + index % base;
+        inv_base_n *= inv_base;
+        index /= base;
+    }
+    (reversed as f64 * inv_base_n).min(1.0 - f64::EPSILON)
+}
+
+/**
+ * the second Sobol dimension, its direction numbers follow v[k + 1] = v[k] ^ (v[k] >> 1)
+ */
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut v = 1u32 << 31;
+    let mut res = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            res ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    res
+}
+
+/**
+ * Laine and Karras' hash based approximation of Owen scrambling
+ */
+fn owen_scramble(v: u32, seed: u32) -> u32 {
+    let mut v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20_adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x0552_6c56);
+    v ^= v.wrapping_mul(0x53a2_2864);
+    v.reverse_bits()
+}
+
+/**
+ * element `i` of a random permutation of 0..n picked by `key`, Kensler 2013
+ */
+fn permutation_element(i: usize, n: usize, key: u64) -> usize {
+    if n <= 1 {
+        return 0;
+    }
+    let (mut i, l, p) = (i as u32, n as u32, key as u32);
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    ((i.wrapping_add(p)) % l) as usize
+}
+
+#[test]
+fn test_samplers() {
+    let samplers: Vec<(&str, Box<dyn Sampler>)> = vec![
+        (
+            "independent",
+            Box::new(IndependentSampler {
+                samples_per_pixel: 64,
+                seed: 1,
+            }),
+        ),
+        (
+            "stratified",
+            Box::new(StratifiedSampler {
+                x_strata: 8,
+                y_strata: 8,
+                jitter: true,
+                seed: 1,
+            }),
+        ),
+        (
+            "halton",
+            Box::new(HaltonSampler {
+                samples_per_pixel: 64,
+                seed: 1,
+            }),
+        ),
+        (
+            "sobol",
+            Box::new(SobolSampler {
+                samples_per_pixel: 64,
+                seed: 1,
+            }),
+        ),
+        (
+            "blue noise",
+            Box::new(BlueNoiseSampler {
+                samples_per_pixel: 64,
+                seed: 1,
+            }),
+        ),
+    ];
+    // integral of x * y over the unit square is 1/4
+    let mut errors = vec![];
+    for (name, sampler) in samplers.iter() {
+        let count = sampler.samples_per_pixel();
+        let mut error = 0.0;
+        for pixel in [(0, 0), (5, 9), (63, 1)].iter() {
+            for dimension in [0, 2, 7].iter() {
+                let mut sum = 0.0;
+                for index in 0..count {
+                    let (x, y) = sampler.sample_2d(*pixel, index, *dimension);
+                    assert!(
+                        (0.0..1.0).contains(&x) && (0.0..1.0).contains(&y),
+                        "{} {} {}",
+                        name,
+                        x,
+                        y
+                    );
+                    assert_eq!(
+                        (x, y),
+                        sampler.sample_2d(*pixel, index, *dimension),
+                        "{} is not reproducible",
+                        name
+                    );
+                    sum += x * y;
+                }
+                error += (sum / count as f64 - 0.25).abs();
+            }
+        }
+        errors.push((*name, error / 9.0));
+    }
+    let independent_error = errors[0].1;
+    for (name, error) in errors.iter().skip(1) {
+        assert!(
+            *error < independent_error,
+            "{} {} vs independent {}",
+            name,
+            error,
+            independent_error
+        );
+    }
+    // stratified 1d samples hit every stratum exactly once
+    let sampler = StratifiedSampler {
+        x_strata: 4,
+        y_strata: 4,
+        jitter: true,
+        seed: 3,
+    };
+    let mut strata: Vec<usize> = (0..16)
+        .map(|i| (sampler.sample_1d((2, 2), i, 4) * 16.0) as usize)
+        .collect();
+    strata.sort_unstable();
+    assert_eq!(strata, (0..16).collect::<Vec<usize>>());
+}
+
+#[test]
+fn test_blue_noise_tile() {
+    let tile = blue_noise_tile();
+    let mut ranks: Vec<usize> = tile
+        .iter()
+        .map(|v| (v * tile.len() as f64) as usize)
+        .collect();
+    ranks.sort_unstable();
+    assert_eq!(ranks, (0..tile.len()).collect::<Vec<usize>>());
+    // the darkest tenth of the pixels should not clump together
+    let n = BLUE_NOISE_SIZE;
+    let mut neighbours = 0;
+    let mut dark = 0;
+    for y in 0..n {
+        for x in 0..n {
+            if tile[y * n + x] < 0.1 {
+                dark += 1;
+                if tile[y * n + (x + 1) % n] < 0.1 || tile[((y + 1) % n) * n + x] < 0.1 {
+                    neighbours += 1;
+                }
+            }
+        }
+    }
+    // white noise would give about 2 * 0.1 of them
+    assert!(
+        (neighbours as f64) < dark as f64 * 0.05,
+        "{} of {} clump",
+        neighbours,
+        dark
+    );
+}
@@ -1,15 +1,29 @@
 
 
+use super::object::Object;
+use crate::Bvh;
+use crate::Light;
 use crate::Material;
+use crate::Mesh;
+use crate::Triangle;
 use crate::Vec4;
 use crate::Vec3;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Scene {
-    pub objects: Vec<Sphere>
+    pub objects: Vec<Primitive>,
+    /**
+     * None until `build_bvh` is called, adding objects invalidates it
+     */
+    pub bvh: Option<Bvh<Primitive>>,
+    /**
+     * emissive spheres and meshes register themselves here as area lights
+     */
+    pub lights: Vec<Light>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Sphere {
     pub origin: Vec4,
     pub radius: f64,
@@ -17,13 +31,106 @@ pub struct Sphere {
     pub color: Vec3,
 }
 
+/**
+ * anything a ray can hit
+ */
+#[derive(Debug, Clone)]
+pub enum Primitive {
+    Sphere(Sphere),
+    Triangle(Triangle),
+}
+
+impl Primitive {
+    pub fn material(&self) -> &Material {
+        match self {
+            Primitive::Sphere(sphere) => &sphere.material,
+            Primitive::Triangle(triangle) => &triangle.mesh.material,
+        }
+    }
+    pub fn color(&self) -> Vec3 {
+        match self {
+            Primitive::Sphere(sphere) => sphere.color,
+            Primitive::Triangle(triangle) => triangle.mesh.color,
+        }
+    }
+    /**
+     * surface directions of growing u and v at `point`, for normal mapping. not normalized
+     */
+    pub fn tangents(&self, point: &Vec3) -> (Vec3, Vec3) {
+        match self {
+            Primitive::Sphere(sphere) => {
+                let mut n = *point - sphere.origin.xyz();
+                n.normalize();
+                let tangent = Vec3::new(-n.z(), 0.0, n.x());
+                (tangent, Vec3::cross(&tangent, &n))
+            }
+            Primitive::Triangle(triangle) => triangle.tangents(),
+        }
+    }
+}
+
 impl Scene {
+    pub fn new() -> Scene {
+        Scene {
+            objects: vec![],
+            bvh: None,
+            lights: vec![],
+        }
+    }
     pub fn add_sphere(&mut self, origin: Vec4, radius: f64, material: Material, color: Vec3) {
-        self.objects.push(Sphere {
+        let emissive = is_emissive(&material);
+        self.objects.push(Primitive::Sphere(Sphere {
             origin,
             radius,
             material,
             color,
-        })
+        }));
+        if emissive {
+            let light = Light::area(&self.objects, vec![self.objects.len() - 1]);
+            self.lights.push(light);
+        }
+        self.bvh = None;
+    }
+    /**
+     * every face of the mesh becomes its own primitive so the bvh can split inside the mesh
+     */
+    pub fn add_mesh(&mut self, mesh: Mesh) {
+        let emissive = is_emissive(&mesh.material);
+        let mesh = Arc::new(mesh);
+        let first = self.objects.len();
+        for face in 0..mesh.indices.len() {
+            self.objects.push(Primitive::Triangle(Triangle {
+                mesh: mesh.clone(),
+                face,
+            }));
+        }
+        if emissive {
+            let light = Light::area(&self.objects, (first..self.objects.len()).collect());
+            self.lights.push(light);
+        }
+        self.bvh = None;
     }
-}
\ No newline at end of file
+    pub fn build_bvh(&mut self) {
+        self.bvh = Some(Bvh::new(&self.objects));
+    }
+}
+
+impl Object for Sphere {
+    fn bounding_box(&self) -> (Vec3, Vec3) {
+        let center = self.origin.xyz();
+        (center + -self.radius, center + self.radius)
+    }
+}
+
+impl Object for Primitive {
+    fn bounding_box(&self) -> (Vec3, Vec3) {
+        match self {
+            Primitive::Sphere(sphere) => sphere.bounding_box(),
+            Primitive::Triangle(triangle) => triangle.bounding_box(),
+        }
+    }
+}
+
+fn is_emissive(material: &Material) -> bool {
+    material.emission.value.iter().any(|c| *c > 0.0)
+}
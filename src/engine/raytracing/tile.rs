@@ -0,0 +1,164 @@
+use crate::Vec3;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+
+/**
+ * a rectangle of the image, rendered as a whole by one worker
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/**
+ * shared flag to stop a running render, checked before each tile
+ */
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/**
+ * splits the frame into tiles and renders them on a pool of worker threads.
+ * `shade` only sees the pixel, randomness comes from a `Sampler` seeded with `seed`,
+ * so the image does not depend on the thread count or the order tiles finish in
+ */
+#[derive(Debug, Clone)]
+pub struct TileRenderer {
+    pub tile_size: usize,
+    pub threads: usize,
+    pub seed: u64,
+    pub cancel: CancelToken,
+}
+
+impl Default for TileRenderer {
+    fn default() -> TileRenderer {
+        TileRenderer {
+            tile_size: 32,
+            threads: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            seed: 0,
+            cancel: CancelToken::default(),
+        }
+    }
+}
+
+impl TileRenderer {
+    pub fn tiles(&self, width: usize, height: usize) -> Vec<Tile> {
+        let size = self.tile_size.max(1);
+        let mut tiles = vec![];
+        for y in (0..height).step_by(size) {
+            for x in (0..width).step_by(size) {
+                tiles.push(Tile {
+                    x,
+                    y,
+                    width: size.min(width - x),
+                    height: size.min(height - y),
+                });
+            }
+        }
+        tiles
+    }
+    /**
+     * calls `shade(x, y)` for every pixel and returns the colors row by row.
+     * `progress(done, total)` is called on this thread after each finished tile.
+     * returns None when the render was cancelled
+     */
+    pub fn render<F, P>(
+        &self,
+        width: usize,
+        height: usize,
+        shade: F,
+        mut progress: P,
+    ) -> Option<Vec<Vec3>>
+    where
+        F: Fn(usize, usize) -> Vec3 + Sync,
+        P: FnMut(usize, usize),
+    {
+        let tiles = self.tiles(width, height);
+        let next_tile = AtomicUsize::new(0);
+        let mut pixels = vec![Vec3::BLACK; width * height];
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::scope(|scope| {
+            for _ in 0..self.threads.max(1) {
+                let sender = sender.clone();
+                let (tiles, next_tile, shade) = (&tiles, &next_tile, &shade);
+                scope.spawn(move || loop {
+                    if self.cancel.is_cancelled() {
+                        break;
+                    }
+                    let tile = match tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) {
+                        Some(tile) => *tile,
+                        None => break,
+                    };
+                    let mut colors = Vec::with_capacity(tile.width * tile.height);
+                    for y in tile.y..tile.y + tile.height {
+                        for x in tile.x..tile.x + tile.width {
+                            colors.push(shade(x, y));
+                        }
+                    }
+                    if sender.send((tile, colors)).is_err() {
+                        break;
+                    }
+                });
+            }
+            // only the workers hold senders now, the loop ends when all of them are done
+            drop(sender);
+
+            for (done, (tile, colors)) in receiver.iter().enumerate() {
+                for (i, color) in colors.into_iter().enumerate() {
+                    let (x, y) = (tile.x + i % tile.width, tile.y + i / tile.width);
+                    pixels[y * width + x] = color;
+                }
+                progress(done + 1, tiles.len());
+            }
+        });
+
+        if self.cancel.is_cancelled() {
+            None
+        } else {
+            Some(pixels)
+        }
+    }
+}
+
+#[test]
+fn test_render_independent_of_thread_count() {
+    let shade = |x: usize, y: usize| Vec3::new(x as f64, y as f64, (x * 31 + y * 17) as f64);
+    let mut images = vec![];
+    for threads in [1, 3, 8].iter() {
+        let renderer = TileRenderer {
+            tile_size: 7,
+            threads: *threads,
+            seed: 5,
+            cancel: CancelToken::default(),
+        };
+        let mut calls = 0;
+        let image = renderer.render(30, 20, shade, |_, _| calls += 1).unwrap();
+        assert_eq!(calls, renderer.tiles(30, 20).len());
+        images.push(image);
+    }
+    for image in images.iter() {
+        for (i, (a, b)) in image.iter().zip(images[0].iter()).enumerate() {
+            assert_eq!((a.x(), a.y()), ((i % 30) as f64, (i / 30) as f64));
+            assert_eq!(a.z(), b.z(), "pixel {} differs between thread counts", i);
+        }
+    }
+
+    let renderer = TileRenderer::default();
+    renderer.cancel.cancel();
+    assert!(renderer.render(30, 20, shade, |_, _| {}).is_none());
+}
@@ -0,0 +1,211 @@
+use super::base::*;
+
+/**
+ * the weights of the three vertices of a primitive at a fragment.
+ * `perspective` interpolates in eye space, `screen` is linear in pixels
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Barycentric {
+    pub perspective: [f64; 3],
+    pub screen: [f64; 3],
+}
+
+impl Barycentric {
+    /**
+     * screen space weights corrected by the 1 / w of each vertex
+     */
+    pub fn new(screen: [f64; 3], inv_w: [f64; 3]) -> Barycentric {
+        let weighted = [
+            screen[0] * inv_w[0],
+            screen[1] * inv_w[1],
+            screen[2] * inv_w[2],
+        ];
+        let sum = weighted[0] + weighted[1] + weighted[2];
+        Barycentric {
+            perspective: weighted.map(|w| w / sum),
+            screen,
+        }
+    }
+    /**
+     * the same weights for both, like along an edge in clip space
+     */
+    pub fn linear(weights: [f64; 3]) -> Barycentric {
+        Barycentric {
+            perspective: weights,
+            screen: weights,
+        }
+    }
+}
+
+/**
+ * data passed from the vertex to the fragment stage
+ */
+pub trait Varying: Clone {
+    /**
+     * the value at `weights` between `vertices`. flat values come from `provoking`,
+     * the last vertex of the primitive before it was clipped
+     */
+    fn interpolate(vertices: [&Self; 3], provoking: &Self, weights: &Barycentric) -> Self;
+}
+
+/**
+ * a programmable pipeline. the associated types are checked at compile time
+ * and every shader gets its own copy of the rasterizer loop.
+ * fragments are shaded on every core, so shaders and their data have to be shareable between threads
+ */
+pub trait Shader: Sync {
+    /**
+     * the input of one vertex
+     */
+    type Vertex;
+    type Varying: Varying + Send + Sync;
+    /**
+     * the same for every vertex and fragment of a draw call
+     */
+    type Uniforms: Sync;
+    /**
+     * whether `fragment` may return None. shaders that never discard are shaded once per pixel,
+     * after the depth test has found the visible triangle
+     */
+    const DISCARDS: bool = true;
+
+    /**
+     * the clip space position of `vertex` and the varyings it hands to the fragment stage
+     */
+    fn vertex(&self, vertex: &Self::Vertex, uniforms: &Self::Uniforms) -> (Vec4, Self::Varying);
+    /**
+     * the rgba color of the fragment at pixel `coord`, None discards it
+     */
+    fn fragment(
+        &self,
+        varying: &Self::Varying,
+        uniforms: &Self::Uniforms,
+        coord: Vec2,
+    ) -> Option<Vec4>;
+    /**
+     * the color of the fragment and a value for each attachment of the frame, in their order.
+     * only multiple render targets need it, the default writes `fragment` and no attachments
+     */
+    fn fragment_outputs(
+        &self,
+        varying: &Self::Varying,
+        uniforms: &Self::Uniforms,
+        coord: Vec2,
+    ) -> Option<(Vec4, Vec<Vec4>)> {
+        self.fragment(varying, uniforms, coord)
+            .map(|color| (color, vec![]))
+    }
+    fn interpolate(
+        &self,
+        vertices: [&Self::Varying; 3],
+        provoking: &Self::Varying,
+        weights: &Barycentric,
+    ) -> Self::Varying {
+        Varying::interpolate(vertices, provoking, weights)
+    }
+}
+
+/**
+ * every fragment gets the value of the provoking vertex
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Flat<T>(pub T);
+
+/**
+ * interpolated linearly in screen space
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoPerspective<T>(pub T);
+
+impl<T: Clone> Varying for Flat<T> {
+    fn interpolate(_: [&Self; 3], provoking: &Self, _: &Barycentric) -> Self {
+        provoking.clone()
+    }
+}
+
+impl<T: Varying> Varying for NoPerspective<T> {
+    fn interpolate(vertices: [&Self; 3], provoking: &Self, weights: &Barycentric) -> Self {
+        NoPerspective(T::interpolate(
+            vertices.map(|v| &v.0),
+            &provoking.0,
+            &Barycentric::linear(weights.screen),
+        ))
+    }
+}
+
+impl Varying for () {
+    fn interpolate(_: [&Self; 3], _: &Self, _: &Barycentric) -> Self {}
+}
+
+impl Varying for f64 {
+    fn interpolate(v: [&Self; 3], _: &Self, weights: &Barycentric) -> Self {
+        let w = weights.perspective;
+        v[0] * w[0] + v[1] * w[1] + v[2] * w[2]
+    }
+}
+
+macro_rules! impl_varying_for_vector {
+    ($($t: ident),*) => {$(
+        impl Varying for $t {
+            fn interpolate(v: [&Self; 3], _: &Self, weights: &Barycentric) -> Self {
+                let w = weights.perspective;
+                let mut res = *v[0];
+                for (i, c) in res.value.iter_mut().enumerate() {
+                    *c = v[0].value[i] * w[0] + v[1].value[i] * w[1] + v[2].value[i] * w[2];
+                }
+                res
+            }
+        }
+    )*};
+}
+
+impl_varying_for_vector!(Vec2, Vec3, Vec4);
+
+macro_rules! impl_varying_for_tuple {
+    ($(($($t: ident $i: tt),*)),*) => {$(
+        impl<$($t: Varying),*> Varying for ($($t,)*) {
+            fn interpolate(v: [&Self; 3], provoking: &Self, weights: &Barycentric) -> Self {
+                ($($t::interpolate([&v[0].$i, &v[1].$i, &v[2].$i], &provoking.$i, weights),)*)
+            }
+        }
+    )*};
+}
+
+impl_varying_for_tuple!(
+    (A 0),
+    (A 0, B 1),
+    (A 0, B 1, C 2),
+    (A 0, B 1, C 2, D 3),
+    (A 0, B 1, C 2, D 3, E 4)
+);
+
+#[test]
+fn test_varying() {
+    // halfway between a vertex at w = 1 and one four times as far away
+    let weights = Barycentric::new([0.5, 0.5, 0.0], [1.0, 0.25, 1.0]);
+    let vertices = [
+        (0.0, NoPerspective(0.0), Flat(1)),
+        (1.0, NoPerspective(1.0), Flat(2)),
+        (2.0, NoPerspective(2.0), Flat(3)),
+    ];
+    let (smooth, screen, flat) = Varying::interpolate(
+        [&vertices[0], &vertices[1], &vertices[2]],
+        &vertices[2],
+        &weights,
+    );
+    assert!((smooth - 0.2).abs() < 1e-9);
+    assert!((screen.0 - 0.5).abs() < 1e-9);
+    assert_eq!(flat.0, 3);
+
+    let colors = [
+        Vec4::new(1.0, 0.0, 0.0, 0.5),
+        Vec4::new(0.0, 1.0, 0.0, 1.0),
+        Vec4::new(0.0, 0.0, 1.0, 1.0),
+    ];
+    let color = Vec4::interpolate(
+        [&colors[0], &colors[1], &colors[2]],
+        &colors[2],
+        &Barycentric::linear([0.5, 0.5, 0.0]),
+    );
+    assert_eq!(color.value, [0.5, 0.5, 0.0, 0.75]);
+}
@@ -0,0 +1,443 @@
+use super::base::*;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    /**
+     * bilinear on the two closest mip levels, blended by the level of detail
+     */
+    Trilinear,
+}
+
+/**
+ * what happens to texture coordinates outside [0, 1]
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+#[derive(Debug)]
+pub enum TextureError {
+    Io(std::io::Error),
+    Png(png::DecodingError),
+    /**
+     * the file is not a ppm or png we can read
+     */
+    Format(String),
+}
+
+impl From<std::io::Error> for TextureError {
+    fn from(err: std::io::Error) -> TextureError {
+        TextureError::Io(err)
+    }
+}
+
+impl From<png::DecodingError> for TextureError {
+    fn from(err: png::DecodingError) -> TextureError {
+        TextureError::Png(err)
+    }
+}
+
+struct MipLevel {
+    width: usize,
+    height: usize,
+    /**
+     * rgba in [0, 1], rows from top to bottom
+     */
+    texels: Vec<[f64; 4]>,
+}
+
+/**
+ * an rgba image with its mip chain. texture coordinates put (0, 0) at the bottom left corner,
+ * like OpenGL and OBJ files, and samples come back as `Vec4(r, g, b, a)` like fragment colors
+ */
+pub struct Texture {
+    levels: Vec<MipLevel>,
+    pub filter: Filter,
+    pub wrap: Wrap,
+}
+
+impl std::fmt::Debug for Texture {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            fmt,
+            "Texture {{ {}x{}, {} levels, {:?}, {:?} }}",
+            self.width(),
+            self.height(),
+            self.levels.len(),
+            self.filter,
+            self.wrap
+        )
+    }
+}
+
+impl Texture {
+    /**
+     * `texels` are rgba rows from top to bottom, the mip chain is built right away.
+     * panics on an empty texture, there is nothing to sample
+     */
+    pub fn new(width: usize, height: usize, texels: Vec<[f64; 4]>) -> Texture {
+        assert!(
+            width > 0 && height > 0,
+            "a texture needs at least one texel"
+        );
+        assert_eq!(
+            texels.len(),
+            width * height,
+            "texel count does not match the size"
+        );
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            texels,
+        }];
+        loop {
+            let last = levels.last().unwrap();
+            if last.width <= 1 && last.height <= 1 {
+                break;
+            }
+            let next = downsample(last);
+            levels.push(next);
+        }
+        Texture {
+            levels,
+            filter: Filter::Bilinear,
+            wrap: Wrap::Repeat,
+        }
+    }
+    /**
+     * reads a ppm (P3 or P6) or png file, the format is told by the magic bytes
+     */
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Texture, TextureError> {
+        let bytes = std::fs::read(path)?;
+        if bytes.starts_with(&[0x89, b'P', b'N', b'G']) {
+            Texture::from_png(&bytes)
+        } else {
+            Texture::from_ppm(&bytes)
+        }
+    }
+    pub fn from_png(bytes: &[u8]) -> Result<Texture, TextureError> {
+        let mut decoder = png::Decoder::new(bytes);
+        // palettes and low bit depths to 8 bits per channel
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            png::ColorType::Indexed => {
+                return Err(TextureError::Format(
+                    "indexed png was not expanded".to_string(),
+                ))
+            }
+        };
+        let (width, height) = (info.width as usize, info.height as usize);
+        let mut texels = Vec::with_capacity(width * height);
+        for row in buffer.chunks(info.line_size).take(height) {
+            for pixel in row.chunks(channels).take(width) {
+                let value = |i: usize| pixel[i] as f64 / 255.0;
+                texels.push(match channels {
+                    1 => [value(0), value(0), value(0), 1.0],
+                    2 => [value(0), value(0), value(0), value(1)],
+                    3 => [value(0), value(1), value(2), 1.0],
+                    _ => [value(0), value(1), value(2), value(3)],
+                });
+            }
+        }
+        Ok(Texture::new(width, height, texels))
+    }
+    pub fn from_ppm(bytes: &[u8]) -> Result<Texture, TextureError> {
+        let format_error = |message: &str| TextureError::Format(message.to_string());
+        // the header is 4 whitespace separated tokens, `#` starts a comment
+        let mut tokens = vec![];
+        let mut position = 0;
+        while tokens.len() < 4 {
+            while position < bytes.len()
+                && (bytes[position].is_ascii_whitespace() || bytes[position] == b'#')
+            {
+                if bytes[position] == b'#' {
+                    while position < bytes.len() && bytes[position] != b'\n' {
+                        position += 1;
+                    }
+                } else {
+                    position += 1;
+                }
+            }
+            let start = position;
+            while position < bytes.len() && !bytes[position].is_ascii_whitespace() {
+                position += 1;
+            }
+            if start == position {
+                return Err(format_error("ppm header is too short"));
+            }
+            tokens.push(String::from_utf8_lossy(&bytes[start..position]).to_string());
+        }
+        let number = |token: &str| {
+            token
+                .parse::<usize>()
+                .map_err(|_| format_error("bad number in ppm header"))
+        };
+        let (width, height, max_value) = (
+            number(&tokens[1])?,
+            number(&tokens[2])?,
+            number(&tokens[3])?,
+        );
+        if max_value == 0 || max_value > 65535 {
+            return Err(format_error("ppm max value out of range"));
+        }
+        if width == 0 || height == 0 {
+            return Err(format_error("ppm size is zero"));
+        }
+        let count = width
+            .checked_mul(height)
+            .and_then(|texels| texels.checked_mul(3))
+            .ok_or_else(|| format_error("ppm size is too large"))?;
+        let values: Vec<usize> = match tokens[0].as_str() {
+            "P3" => String::from_utf8_lossy(&bytes[position..])
+                .split_ascii_whitespace()
+                .take(count)
+                .map(number)
+                .collect::<Result<_, _>>()?,
+            "P6" => {
+                // a single whitespace byte separates the header from the data
+                let data = bytes.get(position + 1..).unwrap_or(&[]);
+                if max_value < 256 {
+                    data.iter().take(count).map(|v| *v as usize).collect()
+                } else {
+                    data.chunks(2)
+                        .take(count)
+                        .map(|v| (v[0] as usize) << 8 | *v.get(1).unwrap_or(&0) as usize)
+                        .collect()
+                }
+            }
+            _ => return Err(format_error("only P3 and P6 ppm files are supported")),
+        };
+        if values.len() < count {
+            return Err(format_error("ppm file ends early"));
+        }
+        let texels = values
+            .chunks(3)
+            .map(|rgb| {
+                let value = |i: usize| rgb[i] as f64 / max_value as f64;
+                [value(0), value(1), value(2), 1.0]
+            })
+            .collect();
+        Ok(Texture::new(width, height, texels))
+    }
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+    pub fn mip_levels(&self) -> usize {
+        self.levels.len()
+    }
+    /**
+     * sample at the full resolution
+     */
+    pub fn sample(&self, u: f64, v: f64) -> Vec4 {
+        self.sample_lod(u, v, 0.0)
+    }
+    /**
+     * `lod` is the mip level to read, fractions blend two levels. only trilinear filtering uses mips
+     */
+    pub fn sample_lod(&self, u: f64, v: f64, lod: f64) -> Vec4 {
+        let [r, g, b, a] = match self.filter {
+            Filter::Nearest => self.nearest(&self.levels[0], u, v),
+            Filter::Bilinear => self.bilinear(&self.levels[0], u, v),
+            Filter::Trilinear => {
+                let lod = lod.clamp(0.0, (self.levels.len() - 1) as f64);
+                let level = lod.floor() as usize;
+                let t = lod - level as f64;
+                let lower = self.bilinear(&self.levels[level], u, v);
+                if t <= 0.0 {
+                    lower
+                } else {
+                    let upper = self.bilinear(&self.levels[level + 1], u, v);
+                    let mut res = [0.0; 4];
+                    for i in 0..4 {
+                        res[i] = lower[i] * (1.0 - t) + upper[i] * t;
+                    }
+                    res
+                }
+            }
+        };
+        Vec4::new(r, g, b, a)
+    }
+    /**
+     * picks the level of detail from how far the texture coordinates move per pixel,
+     * `ddx` and `ddy` are the (u, v) derivatives along the screen axes
+     */
+    pub fn sample_grad(&self, u: f64, v: f64, ddx: (f64, f64), ddy: (f64, f64)) -> Vec4 {
+        let (width, height) = (self.width() as f64, self.height() as f64);
+        let length = |d: (f64, f64)| ((d.0 * width).powi(2) + (d.1 * height).powi(2)).sqrt();
+        let footprint = length(ddx).max(length(ddy));
+        let lod = if footprint > 1.0 {
+            footprint.log2()
+        } else {
+            0.0
+        };
+        self.sample_lod(u, v, lod)
+    }
+    fn texel(&self, level: &MipLevel, x: isize, y: isize) -> [f64; 4] {
+        let x = wrap(self.wrap, x, level.width);
+        let y = wrap(self.wrap, y, level.height);
+        level.texels[y * level.width + x]
+    }
+    fn nearest(&self, level: &MipLevel, u: f64, v: f64) -> [f64; 4] {
+        let x = (u * level.width as f64).floor() as isize;
+        let y = ((1.0 - v) * level.height as f64).floor() as isize;
+        self.texel(level, x, y)
+    }
+    fn bilinear(&self, level: &MipLevel, u: f64, v: f64) -> [f64; 4] {
+        // texel centers sit at half integers
+        let x = u * level.width as f64 - 0.5;
+        let y = (1.0 - v) * level.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        let corners = [
+            (self.texel(level, x0, y0), (1.0 - tx) * (1.0 - ty)),
+            (self.texel(level, x0 + 1, y0), tx * (1.0 - ty)),
+            (self.texel(level, x0, y0 + 1), (1.0 - tx) * ty),
+            (self.texel(level, x0 + 1, y0 + 1), tx * ty),
+        ];
+        let mut res = [0.0; 4];
+        for (texel, weight) in corners.iter() {
+            for i in 0..4 {
+                res[i] += texel[i] * weight;
+            }
+        }
+        res
+    }
+}
+
+fn wrap(mode: Wrap, i: isize, size: usize) -> usize {
+    let size = size as isize;
+    let res = match mode {
+        Wrap::Repeat => i.rem_euclid(size),
+        Wrap::Clamp => i.clamp(0, size - 1),
+        Wrap::Mirror => {
+            let i = i.rem_euclid(2 * size);
+            if i >= size {
+                2 * size - 1 - i
+            } else {
+                i
+            }
+        }
+    };
+    res as usize
+}
+
+/**
+ * box filter to half the size, odd edges reuse the last texel
+ */
+fn downsample(level: &MipLevel) -> MipLevel {
+    let width = (level.width / 2).max(1);
+    let height = (level.height / 2).max(1);
+    let mut texels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0; 4];
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                let sx = (x * 2 + dx).min(level.width - 1);
+                let sy = (y * 2 + dy).min(level.height - 1);
+                let texel = level.texels[sy * level.width + sx];
+                for i in 0..4 {
+                    sum[i] += texel[i] / 4.0;
+                }
+            }
+            texels.push(sum);
+        }
+    }
+    MipLevel {
+        width,
+        height,
+        texels,
+    }
+}
+
+#[test]
+fn test_texture_filtering() {
+    // 2x2: red, green on top, blue, white at the bottom
+    let ppm = "P3\n# a comment\n2 2\n255\n255 0 0  0 255 0\n0 0 255  255 255 255\n";
+    let mut texture = Texture::from_ppm(ppm.as_bytes()).unwrap();
+    assert_eq!(
+        (texture.width(), texture.height(), texture.mip_levels()),
+        (2, 2, 2)
+    );
+
+    texture.filter = Filter::Nearest;
+    let red = texture.sample(0.25, 0.75);
+    assert_eq!((red.x(), red.y(), red.z(), red.w()), (1.0, 0.0, 0.0, 1.0));
+    let blue = texture.sample(0.25, 0.25);
+    assert_eq!((blue.x(), blue.y(), blue.z()), (0.0, 0.0, 1.0));
+
+    texture.filter = Filter::Bilinear;
+    let center = texture.sample(0.5, 0.5);
+    assert!(
+        (center.x() - 0.5).abs() < 1e-9
+            && (center.y() - 0.5).abs() < 1e-9
+            && (center.z() - 0.5).abs() < 1e-9
+    );
+
+    // one texel right of the green texel center
+    texture.filter = Filter::Nearest;
+    let repeat = texture.sample(1.25, 0.75);
+    assert_eq!((repeat.x(), repeat.y()), (1.0, 0.0));
+    texture.wrap = Wrap::Clamp;
+    let clamp = texture.sample(1.25, 0.75);
+    assert_eq!((clamp.x(), clamp.y()), (0.0, 1.0));
+    texture.wrap = Wrap::Mirror;
+    let mirror = texture.sample(1.25, 0.75);
+    assert_eq!((mirror.x(), mirror.y()), (0.0, 1.0));
+    let mirror = texture.sample(1.75, 0.75);
+    assert_eq!((mirror.x(), mirror.y()), (1.0, 0.0));
+
+    // the last mip level is the average, trilinear blends towards it
+    texture.filter = Filter::Trilinear;
+    let average = texture.sample_lod(0.25, 0.75, 1.0);
+    assert!((average.x() - 0.5).abs() < 1e-9 && (average.z() - 0.5).abs() < 1e-9);
+    let half = texture.sample_lod(0.25, 0.75, 0.5);
+    assert!((half.x() - 0.75).abs() < 1e-9, "{:?}", half);
+    let minified = texture.sample_grad(0.25, 0.75, (1.0, 0.0), (0.0, 1.0));
+    assert!((minified.x() - 0.5).abs() < 1e-9);
+}
+
+#[test]
+fn test_texture_decode() {
+    let mut binary = b"P6 1 2 255\n".to_vec();
+    binary.extend_from_slice(&[10, 20, 30, 255, 0, 51]);
+    let texture = Texture::from_ppm(&binary).unwrap();
+    let bottom = texture.sample(0.5, 0.25);
+    assert_eq!((bottom.x(), bottom.y(), bottom.z()), (1.0, 0.0, 0.2));
+
+    let mut png_bytes = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut png_bytes, 2, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer
+            .write_image_data(&[255, 0, 0, 255, 0, 0, 255, 0])
+            .unwrap();
+    }
+    let mut texture = Texture::from_png(&png_bytes).unwrap();
+    texture.filter = Filter::Nearest;
+    let right = texture.sample(0.75, 0.5);
+    assert_eq!((right.z(), right.w()), (1.0, 0.0));
+
+    assert!(Texture::from_ppm(b"P5 1 1 255\n\x00").is_err());
+    assert!(Texture::from_ppm(b"P3 2 2 255\n1 2 3").is_err());
+    assert!(Texture::from_ppm(b"P3 0 5 255\n").is_err());
+    assert!(Texture::from_ppm(format!("P3 {} {} 255\n", usize::MAX, 2).as_bytes()).is_err());
+}
@@ -4,11 +4,55 @@ mod object;
 mod printer;
 mod window;
 
+mod raster_pipeline;
 mod raytrace_pipeline;
 
 use engine::*;
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    // `--raster` draws the cornell box with the raster pipeline instead of ray tracing
+    if args.iter().any(|arg| arg == "--raster") {
+        return raster_pipeline::rasterization();
+    }
+    // `--whitted` renders with the old recursive tracer
+    let integrator = if args.iter().any(|arg| arg == "--whitted") {
+        Integrator::Whitted
+    } else {
+        Integrator::default()
+    };
+    // `--threads N` limits the worker pool, all cores are used by default
+    let mut renderer = TileRenderer::default();
+    if let Some(threads) = arg_value(&args, "--threads").and_then(|n| n.parse().ok()) {
+        renderer.threads = threads;
+    }
+    // `--spp N` samples per pixel, `--sampler independent|stratified|halton|sobol|blue-noise`
+    let samples_per_pixel = arg_value(&args, "--spp")
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(100);
+    let seed = renderer.seed;
+    let sampler: Box<dyn Sampler> = match arg_value(&args, "--sampler").unwrap_or("sobol") {
+        "independent" => Box::new(IndependentSampler { samples_per_pixel, seed }),
+        "stratified" => {
+            // the closest square grid
+            let strata = ((samples_per_pixel as f64).sqrt().round() as usize).max(1);
+            Box::new(StratifiedSampler {
+                x_strata: strata,
+                y_strata: strata,
+                jitter: true,
+                seed,
+            })
+        }
+        "halton" => Box::new(HaltonSampler { samples_per_pixel, seed }),
+        "blue-noise" => Box::new(BlueNoiseSampler { samples_per_pixel, seed }),
+        _ => Box::new(SobolSampler { samples_per_pixel, seed }),
+    };
+    raytrace_pipeline::raytracing(integrator, sampler.as_ref(), &renderer)
+}
 
-    raytrace_pipeline::raytracing()
-}
\ No newline at end of file
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(|value| value.as_str())
+}
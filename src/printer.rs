@@ -10,8 +10,7 @@ use std::fs::write;
 pub fn render_frame_to_stdout(frame: &Frame) {
     for y in 0..frame.height {
         for x in 0..frame.width  {
-            if let Some(pixel) = frame.get(&(x, y)) {
-                let color = pixel.color;
+            if let Some(color) = frame.get_color(&(x, y)) {
                 print!("{}",&"▇".truecolor(color.0, color.1, color.2).to_string())
             }else{
                 print!(" ")
@@ -52,8 +51,8 @@ pub fn render_to_ppm(frame: &Frame) -> String {
     let mut res = format!("P3\n{} {}\n255\n",frame.width,frame.height).to_string();
     for y in 0..frame.height {
         for x in 0..frame.width {
-            if let Some(pixel) = frame.get(&(x, y)) {
-                res += format!("{} {} {}\n", (pixel.color.0), pixel.color.1, pixel.color.2 ).as_str();
+            if let Some(color) = frame.get_color(&(x, y)) {
+                res += format!("{} {} {}\n", (color.0), color.1, color.2 ).as_str();
             } else {
                 res += "0 0 0\n"
             }
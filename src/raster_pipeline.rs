@@ -1,124 +1,106 @@
-
+use crate::camera::Camera;
+use crate::engine::*;
 use crate::printer::render_to_ppm;
-use crate::printer::render_frame_to_stdout;
-use camera::*;
-use engine::*;
-use object::*;
-use window::*;
-
-use std::fs::File;
-use std::io::BufReader;
-
-const TRANSPARENT: Vec4 = Vec4{value:[0.0,0.0,0.0,0.0]};
-
-fn main() {
-
-    let file = File::open("C:\\Users\\chongchong\\Documents\\rust_projects\\soft_render\\cornell_box.obj").unwrap();
-    let mut reader1 = BufReader::new(file);
-    let (models, materials) = tobj::load_obj_buf(&mut reader1, false, |mat_path| {
-        let mtl_file = File::open("C:\\Users\\chongchong\\Documents\\rust_projects\\soft_render\\cornell_box.mtl").unwrap();
-        let mut reader2 = BufReader::new(mtl_file);
-        tobj::load_mtl_buf(&mut reader2)
-    }).unwrap();
 
-    let mut vertices = vec!();
-    let mut colors = vec!();
-    
-    for model in models.into_iter(){
-        for i in model.mesh.indices.iter() {
-            let coord = model.mesh.positions.get((*i as usize)..(*i as usize + 3)).unwrap();
-            vertices.push(Vec4::new(coord[0] as f64, coord[1] as f64, coord[2] as f64, 1.0));
-            if let Some(material_id) = model.mesh.material_id {
-                if let Some(material) = materials.get(material_id) {
-                    colors.push(Vec4::new(material.ambient[0] as f64, material.ambient[1] as f64, material.ambient[2] as f64, 1.0 ));
-                    continue;
-                }
-            }
-            colors.push(Vec4::new(0.0, 0.5, 0.5, 1.0));
+/**
+ * draws the cornell box through the raster pipeline, each material is a flat color
+ */
+pub fn rasterization() {
+    let width_pixel = 1024;
+    let height_pixel = 768;
+
+    let file = std::fs::File::open("cornell_box.obj").unwrap();
+    let mut reader = std::io::BufReader::new(file);
+    // cornell_box.obj also names a cornell_box2.mtl that does not exist, it only costs colors
+    let (models, materials) = tobj::load_obj_buf(&mut reader, true, |mtl_path| {
+        tobj::load_mtl(mtl_path).or_else(|_| Ok((vec![], Default::default())))
+    })
+    .unwrap();
+
+    let mut vertices = vec![];
+    let mut colors = vec![];
+    let mut indices: Vec<u32> = vec![];
+
+    for model in models.into_iter() {
+        let offset = vertices.len() as u32;
+        let color = model
+            .mesh
+            .material_id
+            .and_then(|material_id| materials.get(material_id))
+            .map(|material| {
+                Vec4::new(
+                    material.diffuse[0] as f64,
+                    material.diffuse[1] as f64,
+                    material.diffuse[2] as f64,
+                    1.0,
+                )
+            })
+            .unwrap_or(Vec4::new(0.0, 0.5, 0.5, 1.0));
+        // positions are flat xyz triples, index i starts at positions[i * 3]
+        for coord in model.mesh.positions.chunks_exact(3) {
+            vertices.push(Vec4::new(
+                coord[0] as f64,
+                coord[1] as f64,
+                coord[2] as f64,
+                1.0,
+            ));
+            colors.push(color);
         }
-    };
+        indices.extend(model.mesh.indices.iter().map(|i| i + offset));
+    }
 
-    let camera_position = Vec4::new(0.0, 0.0, 500.0, 1.0);
+    // in front of the open side of the box
+    let camera_position = Vec4::new(278.0, 273.0, -800.0, 1.0);
     let scene_up = Vec4::new(0.0, 1.0, 0.0, 1.0);
-    let mut camera_direction = (Vec4::Origin - camera_position).normalize();
-    
+    let camera_direction = Vec4::new(0.0, 0.0, 1.0, 1.0);
     let mut camera = Camera::new(scene_up, camera_direction, camera_position);
-
+    camera.near = 1.0;
+    camera.far = 3000.0;
+    camera.set_resolution(width_pixel, height_pixel);
 
     let program = Program {
-        vertex_shader: Box::new(move |attributes, uniforms, gl_Position| {
-            let mut projection_matrix = None;
-            let mut model_view_matrix = None;
-            if let Some(value) = uniforms.get(0) {
-                if let ShaderData::Mat4(v) = value {
-                    projection_matrix = Some(v)
-                }
-            }
-            if let Some(value) = uniforms.get(1) {
-                if let ShaderData::Mat4(v) = value {
-                    model_view_matrix = Some(v)
-                }
+        vertex_shader: Box::new(|_, uniforms, position| match (&uniforms[0], &uniforms[1]) {
+            (ShaderData::Mat4(projection), ShaderData::Mat4(model_view)) => {
+                *projection * (*model_view * position)
             }
-            return projection_matrix.unwrap() * &(model_view_matrix.unwrap() * &gl_Position);
+            _ => position,
         }),
-        fragment_shader: Box::new(move |attributes, uniforms, gl_Position| {
-            let mut color = None;
-            if let Some(value) = attributes.get(0) {
-                if let ShaderData::Vec4(v) = value {
-                    color = Some(v)
-                }
-            }
-            return color.unwrap_or(&TRANSPARENT).clone();
+        fragment_shader: Box::new(|attributes, _, _| match &attributes[0] {
+            ShaderData::Vec4(color) => *color,
+            _ => Vec4::new(0.0, 0.0, 0.0, 0.0),
         }),
+        outputs: vec![],
         attributes: vec![Attribute {
             index: 1,
             name: "color".to_string(),
+            interpolation: Interpolation::Flat,
+            layout: None,
         }],
         uniforms: vec![
             Uniform {
                 name: "projectionMatrix".to_string(),
-                value: ShaderData::Mat4(camera.projection_matrix.clone()),
+                value: ShaderData::Mat4(camera.projection_matrix),
             },
             Uniform {
                 name: "modelViewMatrix".to_string(),
-                value: ShaderData::Mat4(camera.view_matrix.clone()),
+                value: ShaderData::Mat4(camera.view_matrix),
             },
         ],
     };
     let mut context = Context {
-        near: 0.1,
-        far: 500.0,
-        current_program: program,
-        current_buffers: vec![],
-        current_frame: Frame::new(1024, 768),
+        current_buffers: vec![vertices.into(), colors.into()],
+        raster: RasterState {
+            // the winding of the obj is not consistent
+            cull_back_faces: false,
+            ..RasterState::default()
+        },
+        ..Context::new(program, Frame::new(width_pixel, height_pixel))
     };
 
-    context.current_buffers.push(vertices);
-    context.current_buffers.push(colors);
-
-    let mut camera_velocity = Vec4::new(0.1, 0.0, 0.0, 0.0);
-
-    // loop {
-        if camera.position.x() >= 1.0 || camera.position.x() <= -1.0 {
-            camera_velocity.value[0] *= -1.0;
-        }
-        camera.position += &camera_velocity;
-        camera.recompute_view_matrix();
-        context.current_frame.clear();
-
-        //update uniforms
-        context.current_program.uniforms[1].value = ShaderData::Mat4(camera.view_matrix.clone());
-
-        context.draw_triangles(0);
-        
-        print!("\x1B[{};{}H", 1, 1);
+    context.clear();
+    context.draw_elements(Topology::Triangles, 0, &Indices::U32(&indices));
 
-        // print!("\r\x1b[2J\r\x1b[H");
+    let ppm = render_to_ppm(&context.current_frame);
 
-        printer::render_frame_to_stdout(&context.current_frame);
-
-        std::thread::sleep(std::time::Duration::from_millis(1000 / FRAME_RATE));
-    // }
+    std::fs::write("./output/raster.ppm", ppm).unwrap();
 }
-
-const FRAME_RATE:u64 = 10;
\ No newline at end of file